@@ -0,0 +1,399 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Derive macro for the kelvin `Content` trait
+//!
+//! Structs are encoded as their fields in declaration order. Enums are
+//! encoded as a discriminant byte, the index of the variant, followed by the
+//! fields of the variant.
+//!
+//! If the type has a type parameter bound by `ByteHash`, it is used as the
+//! hash parameter of the implementation, otherwise the implementation is
+//! generic over all hashes.
+//!
+//! Attributes:
+//!
+//! - `#[kelvin(tag = 7)]` on the type, sets the `Content::type_tag`
+//! - `#[kelvin(skip)]` on a field, skips the field, restoring it with
+//!   `Default::default()`
+//! - `#[kelvin(default = "path::to::fn")]` on a field, skips the field,
+//!   restoring it with the given function
+#![warn(missing_docs)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2, TokenTree};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error,
+    Fields, Generics, Ident, Lit, LitInt, Meta, NestedMeta, Path, Type,
+    TypeParamBound, WherePredicate,
+};
+
+/// Derives `Content` for structs and enums
+#[proc_macro_derive(Content, attributes(kelvin))]
+pub fn derive_content(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+enum Mode {
+    Persist,
+    Skip(Option<Path>),
+}
+
+struct Field {
+    member: syn::Member,
+    ty: Type,
+    mode: Mode,
+}
+
+fn kelvin_attributes(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut nested = vec![];
+    for attr in attrs {
+        if !attr.path.is_ident("kelvin") {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(list) => nested.extend(list.nested),
+            meta => {
+                return Err(Error::new(
+                    meta.span(),
+                    "expected `#[kelvin(...)]`",
+                ))
+            }
+        }
+    }
+    Ok(nested)
+}
+
+fn type_tag(attrs: &[Attribute]) -> syn::Result<Option<LitInt>> {
+    let mut tag = None;
+    for nested in kelvin_attributes(attrs)? {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(ref nv))
+                if nv.path.is_ident("tag") =>
+            {
+                match nv.lit {
+                    Lit::Int(ref int) => tag = Some(int.clone()),
+                    ref lit => {
+                        return Err(Error::new(
+                            lit.span(),
+                            "expected integer type tag",
+                        ))
+                    }
+                }
+            }
+            nested => {
+                return Err(Error::new(
+                    nested.span(),
+                    "unknown kelvin attribute",
+                ))
+            }
+        }
+    }
+    Ok(tag)
+}
+
+fn fields(fields: &Fields) -> syn::Result<Vec<Field>> {
+    let mut result = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        let mut mode = Mode::Persist;
+        for nested in kelvin_attributes(&field.attrs)? {
+            match nested {
+                NestedMeta::Meta(Meta::Path(ref path))
+                    if path.is_ident("skip") =>
+                {
+                    if let Mode::Persist = mode {
+                        mode = Mode::Skip(None)
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(ref nv))
+                    if nv.path.is_ident("default") =>
+                {
+                    match nv.lit {
+                        Lit::Str(ref s) => mode = Mode::Skip(Some(s.parse()?)),
+                        ref lit => {
+                            return Err(Error::new(
+                                lit.span(),
+                                "expected path to default function",
+                            ))
+                        }
+                    }
+                }
+                nested => {
+                    return Err(Error::new(
+                        nested.span(),
+                        "unknown kelvin attribute",
+                    ))
+                }
+            }
+        }
+        let member = match field.ident {
+            Some(ref ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(i.into()),
+        };
+        result.push(Field {
+            member,
+            ty: field.ty.clone(),
+            mode,
+        });
+    }
+    Ok(result)
+}
+
+fn is_bytehash(bound: &TypeParamBound) -> bool {
+    match bound {
+        TypeParamBound::Trait(t) => t
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident == "ByteHash")
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+// Finds the type parameter bound by `ByteHash`, if any
+fn hash_param(generics: &Generics) -> Option<Ident> {
+    for param in generics.type_params() {
+        if param.bounds.iter().any(is_bytehash) {
+            return Some(param.ident.clone());
+        }
+    }
+    for predicate in generics.where_clause.iter().flat_map(|w| &w.predicates) {
+        if let WherePredicate::Type(pt) = predicate {
+            if let Type::Path(ref path) = pt.bounded_ty {
+                if let Some(ident) = path.path.get_ident() {
+                    let is_param =
+                        generics.type_params().any(|p| &p.ident == ident);
+                    if is_param && pt.bounds.iter().any(is_bytehash) {
+                        return Some(ident.clone());
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+fn mentions(tokens: TokenStream2, idents: &[Ident]) -> bool {
+    tokens.into_iter().any(|tt| match tt {
+        TokenTree::Ident(ref ident) => idents.contains(ident),
+        TokenTree::Group(ref group) => mentions(group.stream(), idents),
+        _ => false,
+    })
+}
+
+// Match arm persisting the fields of `path`, after evaluating `prefix`
+fn persist_arm(
+    path: TokenStream2,
+    fields: &[Field],
+    style: &Fields,
+    prefix: TokenStream2,
+    h: &Ident,
+) -> TokenStream2 {
+    let bind =
+        |i: usize| Ident::new(&format!("__field{}", i), Span::call_site());
+
+    let persisted: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| match f.mode {
+            Mode::Persist => true,
+            Mode::Skip(_) => false,
+        })
+        .map(|(i, f)| (bind(i), &f.member))
+        .collect();
+
+    let binds: Vec<_> = persisted.iter().map(|(b, _)| b).collect();
+    let members: Vec<_> = persisted.iter().map(|(_, m)| m).collect();
+
+    let pattern = match style {
+        Fields::Unit => quote!(#path),
+        _ => quote!(#path { #( #members: #binds, )* .. }),
+    };
+
+    quote! {
+        #pattern => {
+            #prefix
+            #( ::kelvin::Content::<#h>::persist(#binds, sink)?; )*
+            Ok(())
+        }
+    }
+}
+
+fn restore_expr(
+    path: TokenStream2,
+    fields: &[Field],
+    style: &Fields,
+    h: &Ident,
+) -> TokenStream2 {
+    let exprs = fields.iter().map(|f| {
+        let member = &f.member;
+        let ty = &f.ty;
+        let expr = match f.mode {
+            Mode::Persist => {
+                quote!(<#ty as ::kelvin::Content<#h>>::restore(source)?)
+            }
+            Mode::Skip(None) => quote!(::std::default::Default::default()),
+            Mode::Skip(Some(ref default)) => quote!(#default()),
+        };
+        quote!(#member: #expr)
+    });
+
+    match style {
+        Fields::Unit => quote!(#path),
+        _ => quote!(#path { #( #exprs, )* }),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let tag = type_tag(&input.attrs)?;
+
+    let mut generics = input.generics.clone();
+    let h = match hash_param(&input.generics) {
+        Some(h) => h,
+        None => {
+            let h = Ident::new("__H", Span::call_site());
+            generics.params.push(parse_quote!(#h: ::kelvin::ByteHash));
+            h
+        }
+    };
+
+    let type_params: Vec<Ident> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .filter(|ident| ident != &h)
+        .collect();
+
+    let (persist_arms, restore, all_fields) = match input.data {
+        Data::Struct(ref data) => {
+            let fields = fields(&data.fields)?;
+            let arm =
+                persist_arm(quote!(#name), &fields, &data.fields, quote!(), &h);
+            let restore =
+                restore_expr(quote!(#name), &fields, &data.fields, &h);
+            (vec![arm], quote!(Ok(#restore)), fields)
+        }
+        Data::Enum(ref data) => {
+            if data.variants.len() > 256 {
+                return Err(Error::new(
+                    name.span(),
+                    "Content can be derived for at most 256 variants",
+                ));
+            }
+            let mut arms = vec![];
+            let mut restores = vec![];
+            let mut all_fields = vec![];
+            for (i, variant) in data.variants.iter().enumerate() {
+                let i = i as u8;
+                let ident = &variant.ident;
+                let fields = fields(&variant.fields)?;
+                arms.push(persist_arm(
+                    quote!(#name::#ident),
+                    &fields,
+                    &variant.fields,
+                    quote!(::std::io::Write::write_all(sink, &[#i])?;),
+                    &h,
+                ));
+                let restore = restore_expr(
+                    quote!(#name::#ident),
+                    &fields,
+                    &variant.fields,
+                    &h,
+                );
+                restores.push(quote!(#i => Ok(#restore),));
+                all_fields.extend(fields);
+            }
+            let invalid = format!("Invalid {} encoding", name);
+            let restore = quote! {
+                let mut tag = [0u8];
+                ::std::io::Read::read_exact(source, &mut tag)?;
+                match tag[0] {
+                    #( #restores )*
+                    _ => Err(::std::io::Error::new(
+                        ::std::io::ErrorKind::InvalidData,
+                        #invalid,
+                    )),
+                }
+            };
+            (arms, restore, all_fields)
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                name.span(),
+                "Content can not be derived for unions",
+            ))
+        }
+    };
+
+    // Require `Content` for every persisted field type mentioning a type
+    // parameter
+    {
+        let where_clause = generics.make_where_clause();
+        let mut bounded: Vec<String> = vec![];
+        for field in &all_fields {
+            if let Mode::Skip(_) = field.mode {
+                continue;
+            }
+            let ty = &field.ty;
+            let tokens = quote!(#ty);
+            let key = tokens.to_string();
+            if mentions(tokens, &type_params) && !bounded.contains(&key) {
+                where_clause
+                    .predicates
+                    .push(parse_quote!(#ty: ::kelvin::Content<#h>));
+                bounded.push(key);
+            }
+        }
+    }
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    // An empty enum can not be matched by reference
+    let persist_match = if persist_arms.is_empty() {
+        quote!(match *self {})
+    } else {
+        quote!(match self { #( #persist_arms )* })
+    };
+
+    let type_tag = tag.map(|tag| {
+        quote! {
+            fn type_tag() -> Option<::kelvin::TypeTag> {
+                Some(#tag)
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::kelvin::Content<#h> for #name #ty_generics
+        #where_clause
+        {
+            fn persist(
+                &mut self,
+                sink: &mut ::kelvin::Sink<#h>,
+            ) -> ::std::io::Result<()> {
+                #persist_match
+            }
+
+            fn restore(
+                source: &mut ::kelvin::Source<#h>,
+            ) -> ::std::io::Result<Self> {
+                #restore
+            }
+
+            #type_tag
+        }
+    })
+}
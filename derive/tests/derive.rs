@@ -0,0 +1,158 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt::Debug;
+
+use kelvin::{Blake2b, ByteHash, Content, Erased, Store};
+use kelvin_derive::Content;
+use kelvin_hamt::DefaultHAMTMap as HAMT;
+
+fn roundtrip<T: Content<Blake2b> + PartialEq + Debug>(mut t: T) -> T {
+    let store = Store::<Blake2b>::ephemeral();
+    let snapshot = store.persist(&mut t).unwrap();
+    let restored = store.restore(&snapshot).unwrap();
+    assert_eq!(t, restored);
+    restored
+}
+
+fn hash<T: Content<Blake2b>>(mut t: T) -> <Blake2b as ByteHash>::Digest {
+    let store = Store::<Blake2b>::ephemeral();
+    store.persist(&mut t).unwrap().into_hash()
+}
+
+#[derive(Clone, Debug, PartialEq, Content)]
+struct Named {
+    a: u32,
+    b: String,
+    c: Vec<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Content)]
+struct Tuple(u64, Option<u8>);
+
+#[derive(Clone, Debug, PartialEq, Content)]
+struct Unit;
+
+#[derive(Clone, Debug, PartialEq, Content)]
+struct Generic<T> {
+    inner: T,
+    list: Vec<T>,
+}
+
+#[derive(Clone, Debug, PartialEq, Content)]
+enum Enum {
+    A,
+    B(u32, u32),
+    C { x: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Content)]
+enum MyOption {
+    Nothing,
+    Something(u32),
+}
+
+fn seven() -> u32 {
+    7
+}
+
+#[derive(Clone, Debug, PartialEq, Content)]
+struct Skipping {
+    kept: u32,
+    #[kelvin(skip)]
+    skipped: u32,
+    #[kelvin(default = "seven")]
+    defaulted: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Content)]
+#[kelvin(tag = 42)]
+struct Tagged(u32);
+
+#[derive(Clone, Content)]
+struct State<H: ByteHash> {
+    map: HAMT<String, u64, H>,
+    counter: u64,
+}
+
+#[test]
+fn structs() {
+    roundtrip(Named {
+        a: 3,
+        b: "hello".into(),
+        c: vec![true, false],
+    });
+    roundtrip(Tuple(8, Some(3)));
+    roundtrip(Unit);
+    roundtrip(Generic {
+        inner: 3u16,
+        list: vec![1, 2, 3],
+    });
+}
+
+#[test]
+fn enums() {
+    roundtrip(Enum::A);
+    roundtrip(Enum::B(1, 2));
+    roundtrip(Enum::C { x: "x".into() });
+}
+
+#[test]
+fn same_encoding_as_hand_written() {
+    assert_eq!(hash(MyOption::Nothing), hash(None::<u32>));
+    assert_eq!(hash(MyOption::Something(3)), hash(Some(3u32)));
+    assert_eq!(hash(Tuple(8, Some(3))), hash((8u64, Some(3u8))));
+}
+
+#[test]
+fn skipped_fields() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let mut skipping = Skipping {
+        kept: 1,
+        skipped: 2,
+        defaulted: 3,
+    };
+
+    assert_eq!(hash(skipping.clone()), hash(1u32));
+
+    let snapshot = store.persist(&mut skipping).unwrap();
+    assert_eq!(
+        store.restore(&snapshot).unwrap(),
+        Skipping {
+            kept: 1,
+            skipped: 0,
+            defaulted: 7,
+        }
+    );
+}
+
+#[test]
+fn type_tag() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let erased = Erased::wrap(Tagged(3), &store).unwrap();
+    assert_eq!(erased.type_tag(), Some(42));
+    assert!(erased.query::<Unit>().is_err());
+    assert_eq!(*erased.query::<Tagged>().unwrap(), Tagged(3));
+}
+
+#[test]
+fn hash_parameter() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let mut state = State {
+        map: HAMT::new(),
+        counter: 1,
+    };
+    state.map.insert("hello".into(), 3).unwrap();
+
+    let snapshot = store.persist(&mut state).unwrap();
+    let restored = store.restore(&snapshot).unwrap();
+
+    assert_eq!(restored.counter, 1);
+    assert_eq!(*restored.map.get("hello").unwrap().unwrap(), 3);
+}
@@ -7,7 +7,7 @@
 use std::borrow::Borrow;
 use std::cmp::Ord;
 use std::io;
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Deref, Sub, SubAssign};
 
 use bytehash::ByteHash;
 use num::{One, Zero};
@@ -20,11 +20,11 @@ use crate::{Compound, Content, Sink, Source};
 
 /// Trait group for Cardinality inner type
 pub trait Counter:
-    Add + Sub + SubAssign + AddAssign + Copy + Zero + One + Ord
+    Add + Sub + SubAssign + AddAssign + Clone + Zero + One + Ord
 {
 }
 impl<T> Counter for T where
-    T: Add + AddAssign + Sub + SubAssign + Copy + Zero + One + Ord
+    T: Add + AddAssign + Sub + SubAssign + Clone + Zero + One + Ord
 {
 }
 
@@ -32,12 +32,20 @@ impl<T> Counter for T where
 #[derive(PartialEq, Eq, Clone)]
 pub struct Cardinality<T>(T);
 
+impl<T> Deref for Cardinality<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 impl<T> Associative for Cardinality<T>
 where
     T: Counter,
 {
     fn op(&mut self, b: &Self) {
-        self.0 += b.0;
+        self.0 += b.0.clone();
     }
 }
 
@@ -79,7 +87,7 @@ where
 {
     fn count(&self) -> U {
         self.annotation()
-            .map(|ann| ann.borrow().0)
+            .map(|ann| ann.borrow().0.clone())
             .unwrap_or_else(U::zero)
     }
 }
@@ -115,7 +123,7 @@ where
                     if let Some(annotation) = child.annotation() {
                         let c: &Cardinality<U> = (*annotation).borrow();
                         if self.0 >= c.0 {
-                            self.0 -= c.0
+                            self.0 -= c.0.clone()
                         } else {
                             return SearchResult::Path(i);
                         }
@@ -9,16 +9,20 @@ use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
 use appendix::Index;
+use atomicwrites::{AllowOverwrite, AtomicFile};
 use bytehash::ByteHash;
 
 use crate::backend::{Backend, PutResult};
+use crate::format::Format;
 
 /// A backend that stores its data in an `appendix` index, and a flat file
 pub struct DiskBackend<H: ByteHash> {
-    index: Index<H::Digest, u64>,
+    // offset and length of each value in the data file
+    index: Index<H::Digest, (u64, u64)>,
     data: File,
     data_path: PathBuf,
     data_offset: u64,
+    format_path: PathBuf,
 }
 
 impl<H: ByteHash> DiskBackend<H> {
@@ -36,6 +40,7 @@ impl<H: ByteHash> DiskBackend<H> {
 
         let index = Index::new(&index_dir)?;
         let data_path = dir.join("data");
+        let format_path = dir.join("format");
 
         let mut data = OpenOptions::new()
             .create(true)
@@ -50,17 +55,25 @@ impl<H: ByteHash> DiskBackend<H> {
             data_path,
             data,
             data_offset,
+            format_path,
         })
     }
+
+    // Discards a partially written value
+    fn rollback(&mut self) -> io::Result<()> {
+        self.data.set_len(self.data_offset)?;
+        self.data.seek(SeekFrom::Start(self.data_offset))?;
+        Ok(())
+    }
 }
 
 impl<H: ByteHash> Backend<H> for DiskBackend<H> {
     fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Box<dyn Read + 'a>> {
         match self.index.get(hash)? {
-            Some(offset) => {
+            Some(&(offset, len)) => {
                 let mut file = File::open(&self.data_path)?;
-                file.seek(SeekFrom::Start(*offset))?;
-                Ok(Box::new(file))
+                file.seek(SeekFrom::Start(offset))?;
+                Ok(Box::new(file.take(len)))
             }
             None => {
                 Err(io::Error::new(io::ErrorKind::NotFound, "Data not found"))
@@ -68,19 +81,57 @@ impl<H: ByteHash> Backend<H> for DiskBackend<H> {
         }
     }
 
+    fn format(&self) -> io::Result<Option<Format>> {
+        if !self.format_path.exists() {
+            return Ok(None);
+        }
+        let mut bytes = vec![];
+        File::open(&self.format_path)?.read_to_end(&mut bytes)?;
+        Format::from_bytes(&bytes).map(Some)
+    }
+
+    fn set_format(&mut self, format: Format) -> io::Result<()> {
+        let af = AtomicFile::new(&self.format_path, AllowOverwrite);
+        af.write(|f| f.write_all(&format.to_bytes()))?;
+        Ok(())
+    }
+
     fn put(
         &mut self,
         hash: H::Digest,
         bytes: Vec<u8>,
     ) -> io::Result<PutResult> {
-        if self.index.insert(hash, self.data_offset)? {
-            // value already present
-            Ok(PutResult::AlreadyThere)
-        } else {
-            self.data.write_all(&bytes)?;
-            self.data_offset += bytes.len() as u64;
-            Ok(PutResult::Ok)
+        let len = bytes.len() as u64;
+        self.put_stream(hash, len, &mut &bytes[..])
+    }
+
+    fn put_stream(
+        &mut self,
+        hash: H::Digest,
+        len: u64,
+        bytes: &mut dyn Read,
+    ) -> io::Result<PutResult> {
+        if self.index.get(&hash)?.is_some() {
+            return Ok(PutResult::AlreadyThere);
         }
+        // The value is only indexed once it is completely written
+        let copied = match io::copy(&mut bytes.take(len), &mut self.data) {
+            Ok(copied) if copied == len => copied,
+            Ok(_) => {
+                self.rollback()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Truncated value stream",
+                ));
+            }
+            Err(e) => {
+                self.rollback()?;
+                return Err(e);
+            }
+        };
+        self.index.insert(hash, (self.data_offset, copied))?;
+        self.data_offset += copied;
+        Ok(PutResult::Ok)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -92,3 +143,32 @@ impl<H: ByteHash> Backend<H> for DiskBackend<H> {
         self.index.on_disk_size() + self.data_offset as usize
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytehash::Blake2b;
+    use tempfile::tempdir;
+
+    #[test]
+    fn truncated_stream() {
+        let dir = tempdir().unwrap();
+        let mut backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+
+        let a = Blake2b::hash(b"a");
+        let b = Blake2b::hash(b"b");
+
+        // the stream ends after 3 of the promised 10 bytes
+        assert!(backend.put_stream(a, 10, &mut &[1u8, 2, 3][..]).is_err());
+        assert!(backend.get(&a).is_err());
+        assert_eq!(backend.data_offset, 0);
+
+        // the failed write left nothing behind
+        backend.put(b, vec![4, 5, 6]).unwrap();
+        backend.put(a, vec![7, 8]).unwrap();
+        let mut bytes = vec![];
+        backend.get(&b).unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, [4, 5, 6]);
+    }
+}
@@ -15,6 +15,7 @@ use wasm_bindgen::{closure::Closure, JsCast};
 use web_sys::Storage;
 
 use crate::backend::{Backend, PutResult};
+use crate::format::Format;
 
 pub struct WebBackend<H: ByteHash> {
     storage: web_sys::Storage,
@@ -44,6 +45,11 @@ impl<H: ByteHash> WebBackend<H> {
             panic!("Could not get local storage")
         }
     }
+
+    // Digest keys are longer, and never collide with this one
+    fn format_key(&self) -> String {
+        format!("{}format", self.name)
+    }
 }
 
 impl<H: ByteHash> Backend<H> for WebBackend<H> {
@@ -75,6 +81,27 @@ impl<H: ByteHash> Backend<H> for WebBackend<H> {
         }
     }
 
+    fn format(&self) -> io::Result<Option<Format>> {
+        match self.storage.get_item(&self.format_key()).unwrap() {
+            Some(value) => {
+                let bytes = decode(&value).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid format encoding",
+                    )
+                })?;
+                Format::from_bytes(&bytes).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set_format(&mut self, format: Format) -> io::Result<()> {
+        let value = encode_config(&format.to_bytes(), STANDARD_NO_PAD);
+        self.storage.set_item(&self.format_key(), &value).unwrap();
+        Ok(())
+    }
+
     fn size(&self) -> usize {
         unimplemented!()
     }
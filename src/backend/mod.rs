@@ -8,6 +8,8 @@ use std::io::{self, Read};
 
 use bytehash::ByteHash;
 
+use crate::format::Format;
+
 mod mem;
 
 #[cfg(feature = "filesystem")]
@@ -30,7 +32,7 @@ pub enum PutResult {
 
 /// Trait to implement custom backends
 pub trait Backend<H: ByteHash> {
-    /// Get a reader from a hash
+    /// Get a reader from a hash, ending with the value
     fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Box<dyn Read + 'a>>;
 
     /// Put the serialized value in the backend.
@@ -40,6 +42,39 @@ pub trait Backend<H: ByteHash> {
         bytes: Vec<u8>,
     ) -> io::Result<PutResult>;
 
+    /// Put a serialized value of `len` bytes, streamed from `bytes`.
+    ///
+    /// Used for large values, the default implementation reads the value
+    /// into memory and calls `put`.
+    fn put_stream(
+        &mut self,
+        digest: H::Digest,
+        len: u64,
+        bytes: &mut dyn Read,
+    ) -> io::Result<PutResult> {
+        let mut buf = Vec::with_capacity(len as usize);
+        bytes.take(len).read_to_end(&mut buf)?;
+        if (buf.len() as u64) < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated value stream",
+            ));
+        }
+        self.put(digest, buf)
+    }
+
+    /// Returns the format recorded with the backend, if any.
+    ///
+    /// Backends that do not persist data across runs need not record it.
+    fn format(&self) -> io::Result<Option<Format>> {
+        Ok(None)
+    }
+
+    /// Records the format of the store using the backend
+    fn set_format(&mut self, _format: Format) -> io::Result<()> {
+        Ok(())
+    }
+
     /// Flush changes to underlying medium
     fn flush(&mut self) -> io::Result<()>;
 
@@ -0,0 +1,698 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::Range;
+
+use bytehash::ByteHash;
+use cache::Cached;
+
+use crate::content::{read_raw_len, write_len, Content};
+use crate::link::Link;
+use crate::sink::Sink;
+use crate::source::Source;
+
+// Chunks are cut where the rolling hash of the data matches the mask, giving
+// an average chunk size of 8 KiB
+const MIN_CHUNK: usize = 2 * 1024;
+const MAX_CHUNK: usize = 64 * 1024;
+const CHUNK_MASK: u64 = !0 << (64 - 13);
+
+// Index nodes end at chunks whose bytes hash to match the mask, giving an
+// average of 32 chunks per index node
+const GROUP_MASK: u8 = 0x1f;
+const MAX_GROUP: usize = 256;
+
+// Appends through `BlobWriter` are buffered up to this size
+const WRITE_BUFFER: usize = 1024 * 1024;
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+const GEAR: [u64; 256] = gear_table();
+
+// Returns the length of the first chunk of `data`, or `None` if more data is
+// needed to find the end of the chunk
+fn chunk_cut(data: &[u8]) -> Option<usize> {
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(MAX_CHUNK).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & CHUNK_MASK == 0 {
+            return Some(i + 1);
+        }
+    }
+    if data.len() >= MAX_CHUNK {
+        Some(MAX_CHUNK)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone)]
+struct Chunk(Vec<u8>);
+
+impl<H: ByteHash> Content<H> for Chunk {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        write_len(sink, self.0.len())?;
+        sink.write_all(&self.0)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let len = read_raw_len(source)?;
+        let len = source.check_len(len)?;
+        let mut bytes = vec![];
+        source.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated Blob chunk",
+            ));
+        }
+        Ok(Chunk(bytes))
+    }
+}
+
+#[derive(Clone)]
+struct ChunkRef<H: ByteHash> {
+    len: u64,
+    chunk: Link<Chunk, H>,
+}
+
+impl<H: ByteHash> ChunkRef<H> {
+    fn new(bytes: Vec<u8>) -> Self {
+        ChunkRef {
+            len: bytes.len() as u64,
+            chunk: Link::new(Chunk(bytes)),
+        }
+    }
+
+    // Loads the chunk, checking it against the stored length
+    fn load(&self) -> io::Result<Cached<'_, Chunk>> {
+        let chunk = self.chunk.get()?;
+        if chunk.0.len() as u64 != self.len {
+            return Err(invalid("Blob chunk length mismatch"));
+        }
+        Ok(chunk)
+    }
+
+    // Whether the chunk ends an index node. Based on the chunk bytes alone,
+    // so that the grouping does not depend on the format of the store.
+    fn ends_group(&self) -> io::Result<bool> {
+        let chunk = self.load()?;
+        Ok(H::hash(&chunk.0).as_ref()[0] & GROUP_MASK == 0)
+    }
+}
+
+impl<H: ByteHash> Content<H> for ChunkRef<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.len.persist(sink)?;
+        self.chunk.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let len = u64::restore(source)?;
+        if len == 0 || len > MAX_CHUNK as u64 {
+            return Err(invalid("Invalid Blob chunk length"));
+        }
+        Ok(ChunkRef {
+            len,
+            chunk: Link::restore(source)?,
+        })
+    }
+}
+
+// A node of the index, referencing a run of chunks
+#[derive(Clone)]
+struct Group<H: ByteHash> {
+    len: u64,
+    chunks: Link<Vec<ChunkRef<H>>, H>,
+}
+
+impl<H: ByteHash> Group<H> {
+    fn new(chunks: Vec<ChunkRef<H>>) -> Self {
+        Group {
+            len: chunks.iter().map(|c| c.len).sum(),
+            chunks: Link::new(chunks),
+        }
+    }
+
+    // Loads the chunk list, checking it against the stored length
+    fn load(&self) -> io::Result<Cached<'_, Vec<ChunkRef<H>>>> {
+        let chunks = self.chunks.get()?;
+        let mut len = 0u64;
+        for chunk_ref in chunks.iter() {
+            len = checked_add(len, chunk_ref.len)?;
+        }
+        if len != self.len {
+            return Err(invalid("Blob index node length mismatch"));
+        }
+        Ok(chunks)
+    }
+}
+
+impl<H: ByteHash> Content<H> for Group<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.len.persist(sink)?;
+        self.chunks.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let len = u64::restore(source)?;
+        if len == 0 {
+            return Err(invalid("Empty Blob index node"));
+        }
+        Ok(Group {
+            len,
+            chunks: Link::restore(source)?,
+        })
+    }
+}
+
+/// A large byte string, split into content-defined chunks stored as
+/// separate nodes under a small index tree.
+///
+/// Chunk boundaries depend only on the data, so equal blobs always hash the
+/// same, and editing a blob only re-persists the chunks around the edit.
+/// Chunks are loaded lazily from the store on access.
+#[derive(Clone)]
+pub struct Blob<H: ByteHash> {
+    len: u64,
+    groups: Vec<Group<H>>,
+}
+
+impl<H: ByteHash> Default for Blob<H> {
+    fn default() -> Self {
+        Blob {
+            len: 0,
+            groups: vec![],
+        }
+    }
+}
+
+impl<H: ByteHash> Blob<H> {
+    /// Creates a new empty `Blob`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `Blob` from a slice of bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut blob = Blob::new();
+        blob.append(bytes).expect("in memory");
+        blob
+    }
+
+    /// Returns the length of the blob in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the blob is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads bytes starting at `offset` into `buf`, returning the number of
+    /// bytes read
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        let mut group_start = 0u64;
+        for group in &self.groups {
+            if read == buf.len() || offset >= self.len {
+                break;
+            }
+            let group_end = checked_add(group_start, group.len)?;
+            if offset + (read as u64) >= group_end {
+                group_start = group_end;
+                continue;
+            }
+            let chunks = group.load()?;
+            let mut chunk_start = group_start;
+            for chunk_ref in chunks.iter() {
+                let pos = offset + read as u64;
+                if read == buf.len() {
+                    break;
+                }
+                let chunk_end = checked_add(chunk_start, chunk_ref.len)?;
+                if pos >= chunk_end {
+                    chunk_start = chunk_end;
+                    continue;
+                }
+                let chunk = chunk_ref.load()?;
+                let from = pos
+                    .checked_sub(chunk_start)
+                    .ok_or_else(|| invalid("Blob chunk out of order"))?
+                    as usize;
+                let n = cmp::min(chunk.0.len() - from, buf.len() - read);
+                buf[read..read + n].copy_from_slice(&chunk.0[from..from + n]);
+                read += n;
+                chunk_start = chunk_end;
+            }
+            group_start = group_end;
+        }
+        Ok(read)
+    }
+
+    /// Reads the whole blob into a `Vec`
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut vec = vec![0; self.len as usize];
+        self.read_at(0, &mut vec)?;
+        Ok(vec)
+    }
+
+    /// Returns a reader over the blob, implementing `Read` and `Seek`
+    pub fn reader(&self) -> BlobReader<'_, H> {
+        BlobReader { blob: self, pos: 0 }
+    }
+
+    /// Returns a buffered writer appending to the blob
+    pub fn writer(&mut self) -> BlobWriter<'_, H> {
+        BlobWriter {
+            blob: self,
+            buf: vec![],
+        }
+    }
+
+    /// Appends bytes to the end of the blob
+    pub fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let len = self.len;
+        self.splice(len..len, bytes)
+    }
+
+    /// Overwrites bytes starting at `offset`, extending the blob if needed
+    pub fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let end = cmp::min(offset + bytes.len() as u64, self.len);
+        self.splice(offset..end, bytes)
+    }
+
+    /// Shortens the blob to `len` bytes
+    pub fn truncate(&mut self, len: u64) -> io::Result<()> {
+        if len < self.len {
+            let end = self.len;
+            self.splice(len..end, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the bytes in `range` with `bytes`
+    pub fn splice(
+        &mut self,
+        range: Range<u64>,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        if range.start > range.end || range.end > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Range out of bounds",
+            ));
+        }
+
+        // Find the index node containing the start of the range, or the last
+        // node when appending
+        let mut first_group = 0;
+        let mut pos = 0;
+        while first_group + 1 < self.groups.len()
+            && pos + self.groups[first_group].len <= range.start
+        {
+            pos += self.groups[first_group].len;
+            first_group += 1;
+        }
+
+        let mut next_group = first_group;
+        let mut old: VecDeque<ChunkRef<H>> = VecDeque::new();
+        self.pull_group(&mut old, &mut next_group)?;
+
+        // Chunks ending before the range are unaffected, except for the
+        // last chunk, which is only ended by the end of the blob
+        let mut kept = vec![];
+        while let Some(chunk_ref) = old.front() {
+            let end = pos + chunk_ref.len;
+            if end <= range.start && end < self.len {
+                pos = end;
+                kept.push(old.pop_front().expect("front exists"));
+            } else {
+                break;
+            }
+        }
+
+        // Collect the old bytes of all chunks overlapping the range
+        let mut old_bytes = vec![];
+        let mut span_end = pos;
+        while span_end < range.end {
+            if old.is_empty() {
+                self.pull_group(&mut old, &mut next_group)?;
+            }
+            let chunk_ref = old.pop_front().expect("range checked");
+            span_end += chunk_ref.len;
+            old_bytes.extend_from_slice(&chunk_ref.load()?.0);
+        }
+
+        let mut pending = old_bytes[..(range.start - pos) as usize].to_vec();
+        pending.extend_from_slice(bytes);
+        pending.extend_from_slice(&old_bytes[(range.end - pos) as usize..]);
+
+        // Re-chunk until a new chunk boundary lines up with an old one
+        let mut chunks = kept;
+        loop {
+            let mut ofs = 0;
+            while let Some(cut) = chunk_cut(&pending[ofs..]) {
+                chunks.push(ChunkRef::new(pending[ofs..ofs + cut].to_vec()));
+                ofs += cut;
+            }
+            pending.drain(..ofs);
+
+            if pending.is_empty() {
+                break;
+            }
+            if old.is_empty() && !self.pull_group(&mut old, &mut next_group)? {
+                // end of the blob
+                chunks.push(ChunkRef::new(mem::replace(&mut pending, vec![])));
+                break;
+            }
+            let chunk_ref = old.pop_front().expect("pulled");
+            pending.extend_from_slice(&chunk_ref.load()?.0);
+        }
+        chunks.extend(old);
+
+        // Re-group until a new index node boundary lines up with an old one
+        let mut chunks = VecDeque::from(chunks);
+        let mut groups = vec![];
+        let mut current = vec![];
+        loop {
+            while let Some(chunk_ref) = chunks.pop_front() {
+                let boundary = chunk_ref.ends_group()?;
+                current.push(chunk_ref);
+                if boundary || current.len() == MAX_GROUP {
+                    groups.push(Group::new(mem::replace(&mut current, vec![])));
+                }
+            }
+            if current.is_empty() {
+                break;
+            }
+            if !self.pull_group(&mut chunks, &mut next_group)? {
+                groups.push(Group::new(current));
+                break;
+            }
+        }
+
+        self.groups.splice(first_group..next_group, groups);
+        self.len = self.len - (range.end - range.start) + bytes.len() as u64;
+        Ok(())
+    }
+
+    // Appends the chunks of the next index node to `chunks`, returning false
+    // if there are no more nodes
+    fn pull_group(
+        &self,
+        chunks: &mut VecDeque<ChunkRef<H>>,
+        next: &mut usize,
+    ) -> io::Result<bool> {
+        match self.groups.get(*next) {
+            Some(group) => {
+                chunks.extend(group.load()?.iter().cloned());
+                *next += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<H: ByteHash> Content<H> for Blob<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.groups.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let groups: Vec<Group<H>> = Vec::restore(source)?;
+        let mut len = 0u64;
+        for group in &groups {
+            len = checked_add(len, group.len)?;
+        }
+        Ok(Blob { len, groups })
+    }
+}
+
+/// A reader over a `Blob`
+pub struct BlobReader<'a, H: ByteHash> {
+    blob: &'a Blob<H>,
+    pos: u64,
+}
+
+impl<'a, H: ByteHash> Read for BlobReader<'a, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.blob.read_at(self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, H: ByteHash> Seek for BlobReader<'a, H> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(ofs) => offset(self.blob.len, ofs),
+            SeekFrom::Current(ofs) => offset(self.pos, ofs),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn checked_add(a: u64, b: u64) -> io::Result<u64> {
+    a.checked_add(b)
+        .ok_or_else(|| invalid("Blob length overflow"))
+}
+
+fn offset(base: u64, ofs: i64) -> Option<u64> {
+    if ofs >= 0 {
+        base.checked_add(ofs as u64)
+    } else {
+        base.checked_sub(ofs.wrapping_neg() as u64)
+    }
+}
+
+/// A buffered writer appending to a `Blob`. Remaining data is appended on
+/// `flush` or when the writer is dropped.
+pub struct BlobWriter<'a, H: ByteHash> {
+    blob: &'a mut Blob<H>,
+    buf: Vec<u8>,
+}
+
+impl<'a, H: ByteHash> Write for BlobWriter<'a, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= WRITE_BUFFER {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.blob.append(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<'a, H: ByteHash> Drop for BlobWriter<'a, H> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{EncodingVersion, Format, HashDomain, Store};
+    use bytehash::Blake2b;
+
+    fn data(len: usize, seed: u64) -> Vec<u8> {
+        // xorshift
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn hash(blob: &Blob<Blake2b>) -> <Blake2b as ByteHash>::Digest {
+        let mut sink = Sink::new_dry(Format::default(), HashDomain::Leaf);
+        blob.clone().persist(&mut sink).unwrap();
+        sink.fin().unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let store = Store::<Blake2b>::ephemeral();
+        let bytes = data(1024 * 1024, 1);
+
+        let mut blob = Blob::from_bytes(&bytes);
+        assert_eq!(blob.len(), bytes.len() as u64);
+        assert!(blob.groups.len() > 1);
+
+        let snapshot = store.persist(&mut blob).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.len(), bytes.len() as u64);
+        assert_eq!(restored.to_vec().unwrap(), bytes);
+
+        for &(ofs, len) in &[(0, 10), (12345, 100_000), (1024 * 1024 - 5, 10)] {
+            let mut buf = vec![0; len];
+            let n = restored.read_at(ofs as u64, &mut buf).unwrap();
+            let end = cmp::min(ofs + len, bytes.len());
+            assert_eq!(&buf[..n], &bytes[ofs..end]);
+        }
+
+        let mut reader = restored.reader();
+        reader.seek(SeekFrom::End(-100)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(&tail[..], &bytes[bytes.len() - 100..]);
+    }
+
+    #[test]
+    fn canonical() {
+        let bytes = data(512 * 1024, 2);
+        let whole = Blob::from_bytes(&bytes);
+
+        let mut streamed = Blob::new();
+        {
+            let mut writer = streamed.writer();
+            for piece in bytes.chunks(1000) {
+                writer.write_all(piece).unwrap();
+                writer.flush().unwrap();
+            }
+        }
+        assert_eq!(hash(&whole), hash(&streamed));
+
+        let mut edited = bytes.clone();
+        edited.splice(1000..2000, data(5000, 3));
+        edited.truncate(400 * 1024);
+
+        let mut blob = whole.clone();
+        blob.splice(1000..2000, &data(5000, 3)).unwrap();
+        blob.truncate(400 * 1024).unwrap();
+
+        assert_eq!(blob.to_vec().unwrap(), edited);
+        assert_eq!(hash(&blob), hash(&Blob::from_bytes(&edited)));
+    }
+
+    #[test]
+    fn grouping_ignores_store_format() {
+        let format = Format {
+            version: EncodingVersion::V1,
+            domain_separation: true,
+            ..Format::default()
+        };
+        let store = Store::<Blake2b>::ephemeral_with_format(format);
+        let bytes = data(2 * 1024 * 1024, 6);
+
+        let snapshot = store.persist(&mut Blob::from_bytes(&bytes)).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.write_at(1024 * 1024, &data(100, 7)).unwrap();
+
+        let mut edited = bytes.clone();
+        edited.splice(1024 * 1024..1024 * 1024 + 100, data(100, 7));
+
+        let mut fresh = Blob::from_bytes(&edited);
+        assert_eq!(
+            store.persist(&mut restored).unwrap().hash(),
+            store.persist(&mut fresh).unwrap().hash()
+        );
+    }
+
+    #[test]
+    fn edits_persist_only_changed_chunks() {
+        let store = Store::<Blake2b>::ephemeral();
+        let mut bytes = data(4 * 1024 * 1024, 4);
+
+        let mut blob = Blob::from_bytes(&bytes);
+        let snapshot = store.persist(&mut blob).unwrap();
+
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.write_at(2 * 1024 * 1024, &[0xff]).unwrap();
+        bytes[2 * 1024 * 1024] = 0xff;
+
+        let size = store.size();
+        let snapshot = store.persist(&mut restored).unwrap();
+        assert!(store.size() - size < 3 * MAX_CHUNK);
+
+        let restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.to_vec().unwrap(), bytes);
+    }
+
+    fn tampered(chunk_len: u64, group_len: u64) -> Blob<Blake2b> {
+        let chunk_ref = ChunkRef {
+            len: chunk_len,
+            chunk: Link::new(Chunk(data(100, 5))),
+        };
+        Blob {
+            len: group_len,
+            groups: vec![Group {
+                len: group_len,
+                chunks: Link::new(vec![chunk_ref]),
+            }],
+        }
+    }
+
+    #[test]
+    fn reject_inconsistent_lengths() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        // chunk shorter than its stored length
+        let snapshot = store.persist(&mut tampered(200, 200)).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        let err = restored.to_vec().err().expect("length mismatch");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // index node longer than its chunks
+        let snapshot = store.persist(&mut tampered(100, 300)).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        let mut buf = [0; 10];
+        let err = restored.read_at(150, &mut buf).err().expect("mismatch");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the untampered encoding still reads back
+        let snapshot = store.persist(&mut tampered(100, 100)).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.to_vec().unwrap(), data(100, 5));
+    }
+}
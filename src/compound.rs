@@ -10,11 +10,16 @@ use std::io;
 use crate::annotations::Combine;
 use crate::branch::{Branch, BranchMut};
 use crate::content::Content;
+use crate::format::Format;
 use crate::handle::Handle;
 use crate::search::Method;
-use crate::sink::Sink;
+use crate::sink::{HashDomain, Sink};
 
-/// A trait for tree-like structures containing leaves
+/// A trait for tree-like structures containing leaves.
+///
+/// Nodes are hashed as tree nodes through handles and `root_hash`,
+/// implementations should return `HashDomain::Node` from
+/// `Content::hash_domain` to hash alike when persisted on their own.
 pub trait Compound<H>: Content<H> + Default
 where
     H: ByteHash,
@@ -57,12 +62,26 @@ where
         BranchMut::new(self, m)
     }
 
-    /// Returns the hash of the Content type.
+    /// Returns the hash of the Content type, in the default `Format`.
     /// This does not write anything to disk, the hashes are simply recursively
     /// computed and cached
+    ///
+    /// Panics if the structure holds nodes persisted in a store of another
+    /// format, see `root_hash_with`.
     fn root_hash(&mut self) -> H::Digest {
-        let mut sink = Sink::new_dry();
-        self.persist(&mut sink).expect("Dry run");
-        sink.fin().expect("Dry run")
+        self.root_hash_with(Format::default())
+            .expect("Nodes persisted in another format")
+    }
+
+    /// Returns the hash of the Content type in the given `Format`, as it
+    /// would be persisted in a store of that format.
+    ///
+    /// Nodes persisted in a store are known by their hash in the format of
+    /// the store, asking for another format fails with `InvalidInput`.
+    fn root_hash_with(&mut self, format: Format) -> io::Result<H::Digest> {
+        // Compound collections are hashed as tree nodes, see `HashDomain`
+        let mut sink = Sink::new_dry(format, HashDomain::Node);
+        self.persist(&mut sink)?;
+        sink.fin()
     }
 }
@@ -4,15 +4,55 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::hash::{BuildHasher, Hash};
 use std::io::{self, Read, Write};
 use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use bytehash::ByteHash;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::sink::Sink;
+use crate::erased::TypeTag;
+use crate::sink::{HashDomain, Sink};
 use crate::source::Source;
-use arrayvec::ArrayVec;
+
+// Upper bound of memory allocated up front when restoring collections
+const PREALLOCATE_BYTES: usize = 1 << 16;
+
+// Writes a collection length in the encoding version of the sink
+pub(crate) fn write_len<H: ByteHash>(
+    sink: &mut Sink<H>,
+    len: usize,
+) -> io::Result<()> {
+    sink.format().version.write_len(sink, len)
+}
+
+// Reads a collection length in the encoding version of the source
+pub(crate) fn read_raw_len<H: ByteHash>(
+    source: &mut Source<H>,
+) -> io::Result<u64> {
+    source.format().version.read_len(source)
+}
+
+// Reads a collection length, checked against the limits of the source
+fn read_len<H: ByteHash>(source: &mut Source<H>) -> io::Result<usize> {
+    let len = read_raw_len(source)?;
+    source.check_len(len)
+}
+
+// Don't trust a decoded length for preallocation beyond a fixed size
+fn capacity<T>(len: usize) -> usize {
+    len.min(PREALLOCATE_BYTES / mem::size_of::<T>().max(1))
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
 
 /// The main trait for content-adressable types, MUST assure a 1-1 mapping between
 /// values of the type and hash digests.
@@ -24,6 +64,33 @@ where
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()>;
     /// Restore the type from a `Source`
     fn restore(source: &mut Source<H>) -> io::Result<Self>;
+    /// Restore a complete node from a `Source`, rejecting trailing bytes
+    /// and any encoding that does not hash back to the digest of the node.
+    /// Used when loading from untrusted backends.
+    fn restore_canonical(source: &mut Source<H>) -> io::Result<Self> {
+        let mut value = Self::restore(source)?;
+        source.finish()?;
+        let mut sink = Sink::new_dry(source.format(), Self::hash_domain());
+        value.persist(&mut sink)?;
+        if sink.fin()? != *source.digest() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Non-canonical encoding",
+            ));
+        }
+        Ok(value)
+    }
+    /// The domain the type is hashed in when persisted as a node of its own.
+    /// Defaults to `HashDomain::Leaf`, compound collections must return
+    /// `HashDomain::Node`.
+    fn hash_domain() -> HashDomain {
+        HashDomain::Leaf
+    }
+    /// Stable identifier of the type, used by `Erased` to detect queries
+    /// and transactions over the wrong type. Defaults to no tag.
+    fn type_tag() -> Option<TypeTag> {
+        None
+    }
 }
 
 impl<T: Content<H>, H: ByteHash> Content<H> for Option<T> {
@@ -42,7 +109,7 @@ impl<T: Content<H>, H: ByteHash> Content<H> for Option<T> {
         source.read_exact(&mut byte)?;
         match byte[0] {
             0 => Ok(None),
-            1 => Ok(Some(T::restore(source)?)),
+            1 => Ok(Some(source.nested(T::restore)?)),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Invalid Option encoding",
@@ -57,7 +124,11 @@ impl<T: Content<H>, H: ByteHash> Content<H> for Box<T> {
     }
 
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
-        Ok(Box::new(T::restore(source)?))
+        Ok(Box::new(source.nested(T::restore)?))
+    }
+
+    fn hash_domain() -> HashDomain {
+        T::hash_domain()
     }
 }
 
@@ -120,23 +191,29 @@ impl<H: ByteHash> Content<H> for bool {
 impl<H: ByteHash> Content<H> for String {
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
         let bytes = self.as_bytes();
-        sink.write_u64::<BigEndian>(bytes.len() as u64)?;
+        write_len(sink, bytes.len())?;
         sink.write_all(&bytes)?;
         Ok(())
     }
 
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
-        let byte_len = source.read_u64::<BigEndian>()?;
-        let mut take = source.take(byte_len);
+        let byte_len = read_len(source)?;
+        let mut take = source.take(byte_len as u64);
         let mut string = String::new();
         take.read_to_string(&mut string)?;
+        if string.len() != byte_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated String encoding",
+            ));
+        }
         Ok(string)
     }
 }
 
 impl<H: ByteHash, T: Content<H>> Content<H> for Vec<T> {
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
-        sink.write_u64::<BigEndian>(self.len() as u64)?;
+        write_len(sink, self.len())?;
         for t in self.iter_mut() {
             t.persist(sink)?
         }
@@ -144,11 +221,14 @@ impl<H: ByteHash, T: Content<H>> Content<H> for Vec<T> {
     }
 
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
-        let len = source.read_u64::<BigEndian>()?;
-        let mut vec = Vec::with_capacity(len as usize);
-        for _ in 0..len {
-            vec.push(T::restore(source)?)
-        }
+        let len = read_len(source)?;
+        let mut vec = Vec::with_capacity(capacity::<T>(len));
+        source.nested(|source| {
+            for _ in 0..len {
+                vec.push(T::restore(source)?)
+            }
+            Ok(())
+        })?;
         Ok(vec)
     }
 }
@@ -178,86 +258,315 @@ number!(i64: read_i64, write_i64);
 number!(i32: read_i32, write_i32);
 number!(i16: read_i16, write_i16);
 
-macro_rules! array {
-    ($number:expr) => {
-        impl<T, H: ByteHash> Content<H> for [T; $number]
+impl<T, H, const N: usize> Content<H> for [T; N]
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        for t in self.iter_mut() {
+            t.persist(sink)?;
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut vec = Vec::with_capacity(capacity::<T>(N));
+        source.nested(|source| {
+            for _ in 0..N {
+                vec.push(T::restore(source)?);
+            }
+            Ok(())
+        })?;
+        match vec.try_into() {
+            Ok(arr) => Ok(arr),
+            Err(_) => unreachable!("Errors out earlier if not full"),
+        }
+    }
+}
+
+macro_rules! tuple {
+    ($($t:ident . $i:tt),+) => {
+        impl<$($t,)+ H> Content<H> for ($($t,)+)
         where
-            T: Content<H>,
+            $($t: Content<H>,)+
+            H: ByteHash,
         {
             fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
-                for i in 0..$number {
-                    self[i].persist(sink)?;
-                }
+                $(self.$i.persist(sink)?;)+
                 Ok(())
             }
 
             fn restore(source: &mut Source<H>) -> io::Result<Self> {
-                let mut arrayvec: ArrayVec<[T; $number]> = ArrayVec::new();
-                for _ in 0..$number {
-                    arrayvec.push(T::restore(source)?);
-                }
-                match arrayvec.into_inner() {
-                    Ok(arr) => Ok(arr),
-                    Err(_) => unreachable!("Errors out earlier if not full"),
-                }
+                source.nested(|source| Ok(($($t::restore(source)?,)+)))
             }
         }
     };
 }
 
-// TODO, find a better way to do this
-array!(0);
-array!(1);
-array!(2);
-array!(3);
-array!(4);
-array!(5);
-array!(6);
-array!(7);
-array!(8);
-array!(9);
-array!(10);
-array!(11);
-array!(12);
-array!(13);
-array!(14);
-array!(15);
-array!(16);
-array!(17);
-array!(18);
-array!(19);
-array!(20);
-array!(21);
-array!(22);
-array!(23);
-array!(24);
-array!(25);
-array!(26);
-array!(27);
-array!(28);
-array!(29);
-array!(30);
-array!(31);
-array!(32);
-
-array!(64);
-array!(128);
-array!(256);
-array!(512);
-array!(1024);
-
-impl<A, B, H> Content<H> for (A, B)
+tuple!(A.0);
+tuple!(A.0, B.1);
+tuple!(A.0, B.1, C.2);
+tuple!(A.0, B.1, C.2, D.3);
+tuple!(A.0, B.1, C.2, D.3, E.4);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5, G.6);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5, G.6, I.7);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5, G.6, I.7, J.8);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5, G.6, I.7, J.8, K.9);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5, G.6, I.7, J.8, K.9, L.10);
+tuple!(A.0, B.1, C.2, D.3, E.4, F.5, G.6, I.7, J.8, K.9, L.10, M.11);
+
+impl<H: ByteHash> Content<H> for i8 {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_i8(*self)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        source.read_i8()
+    }
+}
+
+impl<H: ByteHash> Content<H> for char {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_u32::<BigEndian>(*self as u32)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let code = source.read_u32::<BigEndian>()?;
+        char::from_u32(code).ok_or_else(|| invalid("Invalid char encoding"))
+    }
+}
+
+// Platform dependent sizes are encoded as 64 bit
+impl<H: ByteHash> Content<H> for usize {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_u64::<BigEndian>(*self as u64)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let n = source.read_u64::<BigEndian>()?;
+        usize::try_from(n).map_err(|_| invalid("usize out of range"))
+    }
+}
+
+impl<H: ByteHash> Content<H> for isize {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_i64::<BigEndian>(*self as i64)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let n = source.read_i64::<BigEndian>()?;
+        isize::try_from(n).map_err(|_| invalid("isize out of range"))
+    }
+}
+
+impl<T, E, H> Content<H> for Result<T, E>
+where
+    T: Content<H>,
+    E: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        match *self {
+            Ok(ref mut t) => {
+                sink.write_all(&[0])?;
+                t.persist(sink)
+            }
+            Err(ref mut e) => {
+                sink.write_all(&[1])?;
+                e.persist(sink)
+            }
+        }
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut byte = [0u8];
+        source.read_exact(&mut byte)?;
+        match byte[0] {
+            0 => Ok(Ok(source.nested(T::restore)?)),
+            1 => Ok(Err(source.nested(E::restore)?)),
+            _ => Err(invalid("Invalid Result encoding")),
+        }
+    }
+}
+
+impl<T: Content<H>, H: ByteHash> Content<H> for Rc<T> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        Rc::make_mut(self).persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Rc::new(source.nested(T::restore)?))
+    }
+
+    fn hash_domain() -> HashDomain {
+        T::hash_domain()
+    }
+}
+
+impl<T: Content<H>, H: ByteHash> Content<H> for Arc<T> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        Arc::make_mut(self).persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Arc::new(source.nested(T::restore)?))
+    }
+
+    fn hash_domain() -> HashDomain {
+        T::hash_domain()
+    }
+}
+
+// Encoded as the owned value
+impl<B, H> Content<H> for Cow<'static, B>
+where
+    B: ToOwned + ?Sized + 'static,
+    B::Owned: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.to_mut().persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Cow::Owned(B::Owned::restore(source)?))
+    }
+
+    fn hash_domain() -> HashDomain {
+        B::Owned::hash_domain()
+    }
+}
+
+impl<T: Content<H>, H: ByteHash> Content<H> for VecDeque<T> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        write_len(sink, self.len())?;
+        for t in self.iter_mut() {
+            t.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let len = read_len(source)?;
+        let mut deque = VecDeque::with_capacity(capacity::<T>(len));
+        source.nested(|source| {
+            for _ in 0..len {
+                deque.push_back(T::restore(source)?)
+            }
+            Ok(())
+        })?;
+        Ok(deque)
+    }
+}
+
+// Maps and sets are encoded as their length, followed by the entries sorted
+// by key, so that equal collections always have the same encoding.
+
+fn persist_entries<K, V, H>(
+    mut entries: Vec<(&K, &mut V)>,
+    sink: &mut Sink<H>,
+) -> io::Result<()>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    write_len(sink, entries.len())?;
+    for (k, v) in entries {
+        k.clone().persist(sink)?;
+        v.persist(sink)?;
+    }
+    Ok(())
+}
+
+// Restores entries, rejecting keys that are not strictly increasing
+fn restore_entries<K, V, H>(source: &mut Source<H>) -> io::Result<Vec<(K, V)>>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    let len = read_len(source)?;
+    let mut entries: Vec<(K, V)> = Vec::with_capacity(capacity::<(K, V)>(len));
+    source.nested(|source| {
+        for _ in 0..len {
+            let k = K::restore(source)?;
+            if let Some((last, _)) = entries.last() {
+                if *last >= k {
+                    return Err(invalid("Unsorted map encoding"));
+                }
+            }
+            let v = V::restore(source)?;
+            entries.push((k, v));
+        }
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+impl<K, V, H> Content<H> for BTreeMap<K, V>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        persist_entries(self.iter_mut().collect(), sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(restore_entries(source)?.into_iter().collect())
+    }
+}
+
+impl<K, V, S, H> Content<H> for HashMap<K, V, S>
+where
+    K: Content<H> + Ord + Hash,
+    V: Content<H>,
+    S: BuildHasher + Default + Clone + 'static,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        persist_entries(self.iter_mut().collect(), sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(restore_entries(source)?.into_iter().collect())
+    }
+}
+
+impl<T, H> Content<H> for BTreeSet<T>
+where
+    T: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        let mut units = vec![(); self.len()];
+        persist_entries(self.iter().zip(units.iter_mut()).collect(), sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let entries = restore_entries::<T, (), H>(source)?;
+        Ok(entries.into_iter().map(|(t, _)| t).collect())
+    }
+}
+
+impl<T, S, H> Content<H> for HashSet<T, S>
 where
-    A: Content<H>,
-    B: Content<H>,
+    T: Content<H> + Ord + Hash,
+    S: BuildHasher + Default + Clone + 'static,
     H: ByteHash,
 {
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
-        self.0.persist(sink)?;
-        self.1.persist(sink)
+        let mut units = vec![(); self.len()];
+        persist_entries(self.iter().zip(units.iter_mut()).collect(), sink)
     }
 
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
-        Ok((A::restore(source)?, B::restore(source)?))
+        let entries = restore_entries::<T, (), H>(source)?;
+        Ok(entries.into_iter().map(|(t, _)| t).collect())
     }
 }
@@ -4,23 +4,38 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::any::{self, Any};
+use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::thread;
 
+use arc_swap::ArcSwap;
 use bytehash::ByteHash;
 
 use crate::content::Content;
 use crate::store::Store;
 use crate::{Sink, Source};
 
+/// Stable identifier of a type stored in an `Erased` wrapper
+pub type TypeTag = u64;
+
 /// A type-erased snapshot of a Compound structure.
 ///
 /// Can be used to hide type parameters in complex type definitions
 /// and acts as a kind of `Any` type for other types implementing `Content`
+///
+/// If the wrapped type provides a `Content::type_tag`, queries or
+/// transactions over a different type fail with `InvalidData` instead of
+/// decoding garbage. The tag is only persisted alongside the hash in stores
+/// with `Format::type_tags` set, elsewhere `Erased` is encoded as its hash
+/// alone, and restored values are untagged.
 #[derive(Clone)]
 pub struct Erased<H: ByteHash> {
     hash: H::Digest,
+    tag: Option<TypeTag>,
     store: Store<H>,
 }
 
@@ -31,6 +46,14 @@ pub struct Query<T, H> {
 }
 
 /// Type representing a transaction in progress over an `Erased` wrapper
+///
+/// Changes are only visible in the `Erased` wrapper after `commit`.
+/// Transactions can be nested by starting a transaction on an `Erased`
+/// value contained in the transaction, commits of the inner transaction
+/// only reach the outer `Erased` wrapper when the outer transaction commits.
+///
+/// Dropping a transaction with uncommitted changes is considered a bug, and
+/// panics in debug builds. Use `abort` to explicitly discard changes.
 pub struct Transaction<'a, T, H>
 where
     H: ByteHash,
@@ -38,6 +61,20 @@ where
     inner: T,
     store: Store<H>,
     commit: &'a mut H::Digest,
+    dirty: DirtyGuard,
+}
+
+// Kept separate from `Transaction` so that dropping a transaction does not
+// extend the borrow of the `Erased` wrapper
+struct DirtyGuard(bool);
+
+impl Drop for DirtyGuard {
+    fn drop(&mut self) {
+        debug_assert!(
+            !self.0 || thread::panicking(),
+            "Transaction dropped with uncommitted changes"
+        );
+    }
 }
 
 impl<T, H> Deref for Query<T, H>
@@ -67,6 +104,8 @@ where
     H: ByteHash,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        // Any mutable access counts as an uncommitted change
+        self.dirty.0 = true;
         &mut self.inner
     }
 }
@@ -79,8 +118,27 @@ where
     /// Commits the transaction to the underlying `Erased` wrapper
     pub fn commit(&mut self) -> io::Result<()> {
         *self.commit = self.store.persist(&mut self.inner)?.into_hash();
+        self.dirty.0 = false;
         Ok(())
     }
+
+    /// Discards all changes made since the last commit, and resumes the
+    /// transaction from the committed state
+    pub fn rollback(&mut self) -> io::Result<()> {
+        self.inner = self.store.get_hash(self.commit)?;
+        self.dirty.0 = false;
+        Ok(())
+    }
+
+    /// Ends the transaction, discarding all changes made since the last commit
+    pub fn abort(mut self) {
+        self.dirty.0 = false;
+    }
+
+    /// Returns true if the transaction has changes not yet committed
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.0
+    }
 }
 
 impl<H> Erased<H>
@@ -98,15 +156,37 @@ where
         let snap = store.persist(&mut t)?;
         Ok(Erased {
             hash: snap.into_hash(),
+            tag: T::type_tag(),
             store,
         })
     }
 
+    /// Returns the type tag of the wrapped value, if any
+    pub fn type_tag(&self) -> Option<TypeTag> {
+        self.tag
+    }
+
+    fn check_tag<T: Content<H>>(&self) -> io::Result<()> {
+        match self.tag {
+            Some(tag) if T::type_tag() != Some(tag) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Erased type mismatch: found tag {:#x}, {} has tag {:?}",
+                    tag,
+                    any::type_name::<T>(),
+                    T::type_tag(),
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     /// Constructs a read-only query over the type in the `Erased` wrapper
     pub fn query<T>(&self) -> io::Result<Query<T, H>>
     where
         T: Content<H>,
     {
+        self.check_tag::<T>()?;
         let inner = self.store.get_hash(&self.hash)?;
         Ok(Query {
             inner,
@@ -119,11 +199,13 @@ where
     where
         T: Content<H>,
     {
+        self.check_tag::<T>()?;
         let inner = self.store.get_hash(&self.hash)?;
         Ok(Transaction {
             inner,
             store: self.store.clone(),
             commit: &mut self.hash,
+            dirty: DirtyGuard(false),
         })
     }
 }
@@ -133,22 +215,249 @@ where
     H: ByteHash,
 {
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
-        sink.write_all(self.hash.as_ref())
+        sink.write_all(self.hash.as_ref())?;
+        if sink.format().type_tags {
+            self.tag.persist(sink)?;
+        }
+        Ok(())
     }
 
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
         let mut hash = H::Digest::default();
         source.read_exact(hash.as_mut())?;
+        let tag = if source.format().type_tags {
+            Option::restore(source)?
+        } else {
+            None
+        };
         Ok(Erased {
             hash,
+            tag,
             store: source.store().clone(),
         })
     }
 }
 
+/// The result of committing a `SharedTransaction`
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommitResult {
+    /// The transaction was committed
+    Ok,
+    /// Another transaction committed since this one started, nothing was
+    /// written to the `SharedErased` cell
+    Conflict,
+}
+
+/// A thread-safe cell holding the current state of an `Erased` value.
+///
+/// Transactions work on a snapshot of the value, and on commit the cell is
+/// only updated if no other transaction committed in the meantime. The
+/// digest is swapped with an atomic compare and swap, without locking.
+#[derive(Clone)]
+pub struct SharedErased<H: ByteHash> {
+    hash: Arc<ArcSwap<H::Digest>>,
+    tag: Option<TypeTag>,
+    store: Store<H>,
+}
+
+/// Type representing a transaction in progress over a `SharedErased` cell
+pub struct SharedTransaction<T, H>
+where
+    H: ByteHash,
+{
+    inner: T,
+    // the digest the transaction started from, compared by address on commit
+    base: Arc<H::Digest>,
+    shared: SharedErased<H>,
+}
+
+impl<T, H> Deref for SharedTransaction<T, H>
+where
+    H: ByteHash,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T, H> DerefMut for SharedTransaction<T, H>
+where
+    H: ByteHash,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T, H> SharedTransaction<T, H>
+where
+    H: ByteHash,
+    T: Content<H>,
+{
+    /// Attempts to commit the transaction to the `SharedErased` cell.
+    ///
+    /// Succeeds only if the cell still holds the state the transaction
+    /// started from, otherwise returns `CommitResult::Conflict`.
+    pub fn commit(&mut self) -> io::Result<CommitResult> {
+        let hash = self.shared.store.persist(&mut self.inner)?.into_hash();
+        let hash = Arc::new(hash);
+        let previous =
+            self.shared.hash.compare_and_swap(&self.base, hash.clone());
+        if Arc::ptr_eq(&previous, &self.base) {
+            self.base = hash;
+            Ok(CommitResult::Ok)
+        } else {
+            Ok(CommitResult::Conflict)
+        }
+    }
+}
+
+impl<H> From<Erased<H>> for SharedErased<H>
+where
+    H: ByteHash,
+{
+    fn from(erased: Erased<H>) -> Self {
+        SharedErased {
+            hash: Arc::new(ArcSwap::from_pointee(erased.hash)),
+            tag: erased.tag,
+            store: erased.store,
+        }
+    }
+}
+
+impl<H> SharedErased<H>
+where
+    H: ByteHash,
+{
+    /// Construct a new shared cell holding `t`
+    pub fn wrap<T: Content<H>>(t: T, store: &Store<H>) -> io::Result<Self> {
+        Ok(Erased::wrap(t, store)?.into())
+    }
+
+    /// Returns the current state of the cell as an `Erased` value
+    pub fn snapshot(&self) -> Erased<H> {
+        Erased {
+            hash: **self.hash.load(),
+            tag: self.tag,
+            store: self.store.clone(),
+        }
+    }
+
+    /// Constructs a read-only query over the current state of the cell
+    pub fn query<T>(&self) -> io::Result<Query<T, H>>
+    where
+        T: Content<H>,
+    {
+        self.snapshot().query()
+    }
+
+    /// Constructs a transaction over the current state of the cell
+    pub fn transaction<T>(&self) -> io::Result<SharedTransaction<T, H>>
+    where
+        T: Content<H>,
+    {
+        let base = self.hash.load_full();
+        let snapshot = Erased {
+            hash: *base,
+            tag: self.tag,
+            store: self.store.clone(),
+        };
+        snapshot.check_tag::<T>()?;
+        Ok(SharedTransaction {
+            inner: self.store.get_hash(&base)?,
+            base,
+            shared: self.clone(),
+        })
+    }
+
+    /// Applies `f` to the value in a transaction, retrying on conflicts
+    /// until the transaction is committed
+    pub fn update<T, F, R>(&self, mut f: F) -> io::Result<R>
+    where
+        T: Content<H>,
+        F: FnMut(&mut T) -> io::Result<R>,
+    {
+        loop {
+            let mut transaction = self.transaction::<T>()?;
+            let result = f(&mut *transaction)?;
+            if transaction.commit()? == CommitResult::Ok {
+                return Ok(result);
+            }
+        }
+    }
+}
+
+type Decoder<H> = fn(&Erased<H>) -> io::Result<Box<dyn Any>>;
+
+fn decode<T, H>(erased: &Erased<H>) -> io::Result<Box<dyn Any>>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    Ok(Box::new(erased.store.get_hash::<T>(&erased.hash)?))
+}
+
+/// Registry mapping type tags to decoders, for dynamic inspection of
+/// `Erased` values of unknown type
+pub struct TypeRegistry<H: ByteHash> {
+    decoders: HashMap<TypeTag, (&'static str, Decoder<H>)>,
+}
+
+impl<H> Default for TypeRegistry<H>
+where
+    H: ByteHash,
+{
+    fn default() -> Self {
+        TypeRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+}
+
+impl<H> TypeRegistry<H>
+where
+    H: ByteHash,
+{
+    /// Creates a new empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a decoder for `T` under its type tag.
+    /// Panics if `T` has no type tag.
+    pub fn register<T: Content<H>>(&mut self) {
+        let tag = T::type_tag().expect("Only tagged types can be registered");
+        self.decoders
+            .insert(tag, (any::type_name::<T>(), decode::<T, H>));
+    }
+
+    /// Returns the name of the type registered for `tag`, if any
+    pub fn name(&self, tag: TypeTag) -> Option<&'static str> {
+        self.decoders.get(&tag).map(|(name, _)| *name)
+    }
+
+    /// Decodes the value in the `Erased` wrapper with the decoder registered
+    /// for its type tag
+    pub fn decode(&self, erased: &Erased<H>) -> io::Result<Box<dyn Any>> {
+        let tag = erased.tag.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Erased has no type tag")
+        })?;
+        match self.decoders.get(&tag) {
+            Some((_, decoder)) => decoder(erased),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No decoder registered for type tag {:#x}", tag),
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::Format;
     use bytehash::Blake2b;
 
     #[test]
@@ -216,4 +525,247 @@ mod test {
 
         assert_eq!(*restored.b.query::<u32>().unwrap(), 12);
     }
+
+    #[test]
+    fn abort_and_rollback() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut erased = Erased::wrap(10u32, &store).unwrap();
+
+        let mut trans = erased.transaction::<u32>().unwrap();
+        *trans = 11;
+        assert!(trans.is_dirty());
+        trans.rollback().unwrap();
+        assert_eq!(*trans, 10);
+        assert!(!trans.is_dirty());
+
+        *trans = 12;
+        trans.commit().unwrap();
+        *trans = 13;
+        trans.abort();
+
+        assert_eq!(*erased.query::<u32>().unwrap(), 12);
+    }
+
+    #[test]
+    fn nested_transactions() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let double = Double {
+            a: Erased::wrap(1u32, &store).unwrap(),
+            b: Erased::wrap(2u32, &store).unwrap(),
+        };
+
+        let mut outer = Erased::wrap(double, &store).unwrap();
+
+        {
+            let mut trans = outer.transaction::<Double<_>>().unwrap();
+
+            let mut inner = trans.a.transaction::<u32>().unwrap();
+            *inner = 100;
+            inner.commit().unwrap();
+
+            // an aborted inner transaction leaves the outer one untouched
+            let mut inner = trans.b.transaction::<u32>().unwrap();
+            *inner = 200;
+            inner.abort();
+
+            // inner commits are visible within the outer transaction
+            assert_eq!(*trans.a.query::<u32>().unwrap(), 100);
+            assert_eq!(*trans.b.query::<u32>().unwrap(), 2);
+
+            // but do not reach the outer wrapper unless it commits
+            trans.abort();
+        }
+
+        let query = outer.query::<Double<_>>().unwrap();
+        assert_eq!(*query.a.query::<u32>().unwrap(), 1);
+
+        {
+            let mut trans = outer.transaction::<Double<_>>().unwrap();
+            let mut inner = trans.a.transaction::<u32>().unwrap();
+            *inner = 100;
+            inner.commit().unwrap();
+            trans.commit().unwrap();
+        }
+
+        let query = outer.query::<Double<_>>().unwrap();
+        assert_eq!(*query.a.query::<u32>().unwrap(), 100);
+        assert_eq!(*query.b.query::<u32>().unwrap(), 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "uncommitted changes")]
+    fn uncommitted_drop() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut erased = Erased::wrap(10u32, &store).unwrap();
+
+        let mut trans = erased.transaction::<u32>().unwrap();
+        *trans = 11;
+    }
+
+    #[test]
+    fn shared_conflict() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let shared = SharedErased::wrap(0u32, &store).unwrap();
+
+        let mut a = shared.transaction::<u32>().unwrap();
+        let mut b = shared.transaction::<u32>().unwrap();
+
+        *a = 1;
+        *b = 2;
+
+        assert_eq!(a.commit().unwrap(), CommitResult::Ok);
+        assert_eq!(b.commit().unwrap(), CommitResult::Conflict);
+
+        assert_eq!(*shared.query::<u32>().unwrap(), 1);
+
+        // a transaction can keep committing on top of its own commits
+        *a = 3;
+        assert_eq!(a.commit().unwrap(), CommitResult::Ok);
+        assert_eq!(*shared.query::<u32>().unwrap(), 3);
+    }
+
+    #[test]
+    fn shared_threads() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let shared = SharedErased::wrap(0u32, &store).unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        shared
+                            .update(|n: &mut u32| {
+                                *n += 1;
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*shared.query::<u32>().unwrap(), 400);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Meters(u32);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Seconds(u32);
+
+    impl<H: ByteHash> Content<H> for Meters {
+        fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<H>) -> io::Result<Self> {
+            Ok(Meters(u32::restore(source)?))
+        }
+
+        fn type_tag() -> Option<TypeTag> {
+            Some(1)
+        }
+    }
+
+    impl<H: ByteHash> Content<H> for Seconds {
+        fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<H>) -> io::Result<Self> {
+            Ok(Seconds(u32::restore(source)?))
+        }
+
+        fn type_tag() -> Option<TypeTag> {
+            Some(2)
+        }
+    }
+
+    #[test]
+    fn tagged_mismatch() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut erased = Erased::wrap(Meters(3), &store).unwrap();
+
+        assert_eq!(erased.type_tag(), Some(1));
+        assert_eq!(*erased.query::<Meters>().unwrap(), Meters(3));
+
+        let err = erased.query::<Seconds>().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = erased.transaction::<u32>().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // untagged values are not checked
+        let untagged = Erased::wrap(3u32, &store).unwrap();
+        assert_eq!(*untagged.query::<Seconds>().unwrap(), Seconds(3));
+    }
+
+    #[test]
+    fn tag_survives_restore() {
+        let store = Store::<Blake2b>::ephemeral_with_format(Format {
+            type_tags: true,
+            ..Format::default()
+        });
+
+        let mut double = Double {
+            a: Erased::wrap(Meters(13), &store).unwrap(),
+            b: Erased::wrap(14u32, &store).unwrap(),
+        };
+
+        let snapshot = store.persist(&mut double).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        assert_eq!(restored.a.type_tag(), Some(1));
+        assert_eq!(restored.b.type_tag(), None);
+        assert!(restored.a.query::<Seconds>().is_err());
+    }
+
+    #[test]
+    fn untagged_encoding() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut erased = Erased::wrap(Meters(3), &store).unwrap();
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(erased.hash.as_ref());
+
+        // without type tags in the format, only the hash is written
+        let encoded = store.persist(&mut erased).unwrap();
+        let raw = store.persist(&mut digest).unwrap();
+        assert_eq!(encoded.hash(), raw.hash());
+
+        let restored = store.restore(&encoded).unwrap();
+        assert_eq!(restored.type_tag(), None);
+        assert_eq!(*restored.query::<Meters>().unwrap(), Meters(3));
+    }
+
+    #[test]
+    fn registry() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut registry = TypeRegistry::new();
+        registry.register::<Meters>();
+        registry.register::<Seconds>();
+
+        let erased = Erased::wrap(Seconds(9), &store).unwrap();
+
+        assert!(registry.name(2).unwrap().ends_with("Seconds"));
+
+        let decoded = registry.decode(&erased).unwrap();
+        assert_eq!(decoded.downcast_ref::<Seconds>(), Some(&Seconds(9)));
+
+        let untagged = Erased::wrap(9u32, &store).unwrap();
+        assert!(registry.decode(&untagged).is_err());
+    }
 }
@@ -0,0 +1,189 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io::{self, Read, Write};
+
+use bytehash::ByteHash;
+
+use crate::content::Content;
+use crate::sink::Sink;
+use crate::source::Source;
+
+/// Wrapper for floats that rejects NaN and negative zero on persist and
+/// restore, instead of canonicalizing them like the plain `f32` and `f64`
+/// implementations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct StrictFloat<T>(pub T);
+
+impl<T> From<T> for StrictFloat<T> {
+    fn from(t: T) -> Self {
+        StrictFloat(t)
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) trait Float: Copy {
+    // All NaNs are encoded as the same quiet NaN, and negative zero as
+    // positive zero
+    fn canonical(self) -> Self;
+    fn is_strict(self) -> bool;
+}
+
+macro_rules! float {
+    ($t:ident : $bytes:expr) => {
+        impl Float for $t {
+            fn canonical(self) -> Self {
+                if self.is_nan() {
+                    std::$t::NAN
+                } else if self == 0.0 {
+                    0.0
+                } else {
+                    self
+                }
+            }
+
+            fn is_strict(self) -> bool {
+                self.to_bits() == self.canonical().to_bits() && !self.is_nan()
+            }
+        }
+
+        impl<H: ByteHash> Content<H> for $t {
+            fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+                sink.write_all(&self.canonical().to_be_bytes())
+            }
+
+            fn restore(source: &mut Source<H>) -> io::Result<Self> {
+                let mut bytes = [0u8; $bytes];
+                source.read_exact(&mut bytes)?;
+                let f = $t::from_be_bytes(bytes);
+                if f.to_bits() != f.canonical().to_bits() {
+                    return Err(invalid("Non-canonical float encoding"));
+                }
+                Ok(f)
+            }
+        }
+
+        impl<H: ByteHash> Content<H> for StrictFloat<$t> {
+            fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+                if !self.0.is_strict() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "NaN or negative zero in StrictFloat",
+                    ));
+                }
+                sink.write_all(&self.0.to_be_bytes())
+            }
+
+            fn restore(source: &mut Source<H>) -> io::Result<Self> {
+                let f = $t::restore(source)?;
+                if !f.is_strict() {
+                    return Err(invalid("NaN in StrictFloat"));
+                }
+                Ok(StrictFloat(f))
+            }
+        }
+    };
+}
+
+float!(f32: 4);
+float!(f64: 8);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::tests::arbitrary::{self, Arbitrary, Unstructured};
+    use crate::tests::fuzz_content;
+    use crate::Store;
+    use bytehash::Blake2b;
+
+    // Floats compared by their canonical encoding
+    macro_rules! fuzzed {
+        ($name:ident, $t:ty) => {
+            #[derive(Clone, Debug)]
+            struct $name($t);
+
+            impl PartialEq for $name {
+                fn eq(&self, other: &Self) -> bool {
+                    self.0.canonical().to_bits()
+                        == other.0.canonical().to_bits()
+                }
+            }
+
+            impl Arbitrary for $name {
+                fn arbitrary(
+                    u: &mut Unstructured<'_>,
+                ) -> arbitrary::Result<Self> {
+                    Ok($name(<$t>::arbitrary(u)?))
+                }
+            }
+
+            impl<H: ByteHash> Content<H> for $name {
+                fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+                    self.0.persist(sink)
+                }
+
+                fn restore(source: &mut Source<H>) -> io::Result<Self> {
+                    Ok($name(<$t>::restore(source)?))
+                }
+            }
+        };
+    }
+
+    fuzzed!(F32, f32);
+    fuzzed!(F64, f64);
+
+    #[test]
+    fn fuzz() {
+        fuzz_content::<F32, Blake2b>();
+        fuzz_content::<F64, Blake2b>();
+    }
+
+    #[test]
+    fn canonicalize() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let zero = store.persist(&mut 0.0f64).unwrap();
+        assert_eq!(store.persist(&mut -0.0f64).unwrap().hash(), zero.hash());
+
+        let mut nan = std::f32::NAN;
+        let nan = store.persist(&mut nan).unwrap();
+        let mut payload = f32::from_bits(0xffc0_1234);
+        assert!(payload.is_nan());
+        assert_eq!(store.persist(&mut payload).unwrap().hash(), nan.hash());
+        assert!(store.restore(&nan).unwrap().is_nan());
+
+        // non-canonical encodings are rejected
+        for &bits in &[0x8000_0000_0000_0000u64, 0x7ff0_0000_0000_0001] {
+            let snapshot = store.persist(&mut { bits }).unwrap();
+            assert!(store
+                .snapshot_from_digest::<f64>(snapshot.hash())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn strict() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut value = StrictFloat(1.5f64);
+        let snapshot = store.persist(&mut value).unwrap();
+        assert_eq!(store.restore(&snapshot).unwrap(), value);
+        assert_eq!(store.persist(&mut 1.5f64).unwrap().hash(), snapshot.hash());
+
+        assert!(store.persist(&mut StrictFloat(-0.0f64)).is_err());
+        assert!(store.persist(&mut StrictFloat(std::f32::NAN)).is_err());
+
+        let mut nan = std::f64::NAN;
+        let nan = store.persist(&mut nan).unwrap();
+        assert!(store
+            .snapshot_from_digest::<StrictFloat<f64>>(nan.hash())
+            .is_err());
+    }
+}
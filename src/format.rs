@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::varint::{read_varint, write_varint};
+
+/// Version of the encoding used by the built-in `Content` implementations.
+///
+/// Changing the version changes root hashes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodingVersion {
+    /// Strings and collections are prefixed with a fixed width 64 bit length
+    V0 = 0,
+    /// Strings and collections are prefixed with a `Varint` length
+    V1 = 1,
+}
+
+impl Default for EncodingVersion {
+    fn default() -> Self {
+        EncodingVersion::V0
+    }
+}
+
+impl EncodingVersion {
+    pub(crate) fn write_len<W: Write>(
+        self,
+        w: &mut W,
+        len: usize,
+    ) -> io::Result<()> {
+        match self {
+            EncodingVersion::V0 => w.write_u64::<BigEndian>(len as u64),
+            EncodingVersion::V1 => write_varint(w, len as u128),
+        }
+    }
+
+    pub(crate) fn read_len<R: Read>(self, r: &mut R) -> io::Result<u64> {
+        match self {
+            EncodingVersion::V0 => r.read_u64::<BigEndian>(),
+            EncodingVersion::V1 => read_varint(r, 64).map(|len| len as u64),
+        }
+    }
+}
+
+/// The format of the data in a store, used by every `Sink` and `Source` of
+/// the store.
+///
+/// The format is recorded with persistent stores when they are created. The
+/// default format is the original encoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Format {
+    /// Encoding of the built-in `Content` implementations
+    pub version: EncodingVersion,
+    /// Appends the `HashDomain` of every node to its bytes when hashing, so
+    /// that a leaf value can never hash to the same digest as a tree node
+    pub domain_separation: bool,
+    /// Persists the type tag of `Erased` values along with their hash
+    pub type_tags: bool,
+}
+
+// Flags of the recorded format
+const DOMAIN_SEPARATION: u8 = 1;
+const TYPE_TAGS: u8 = 2;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Format {
+    /// Encodes the format for recording with a store
+    pub fn to_bytes(&self) -> [u8; 2] {
+        let mut flags = 0;
+        if self.domain_separation {
+            flags |= DOMAIN_SEPARATION;
+        }
+        if self.type_tags {
+            flags |= TYPE_TAGS;
+        }
+        [self.version as u8, flags]
+    }
+
+    /// Decodes a recorded format, rejecting unknown versions and flags
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let (version, flags) = match bytes {
+            [version, flags] => (*version, *flags),
+            _ => return Err(invalid("Invalid format encoding")),
+        };
+        let version = match version {
+            0 => EncodingVersion::V0,
+            1 => EncodingVersion::V1,
+            _ => return Err(invalid("Unknown encoding version")),
+        };
+        if flags & !(DOMAIN_SEPARATION | TYPE_TAGS) != 0 {
+            return Err(invalid("Unknown format flags"));
+        }
+        Ok(Format {
+            version,
+            domain_separation: flags & DOMAIN_SEPARATION != 0,
+            type_tags: flags & TYPE_TAGS != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recorded_bytes() {
+        let formats = [
+            Format::default(),
+            Format {
+                version: EncodingVersion::V1,
+                ..Format::default()
+            },
+            Format {
+                domain_separation: true,
+                ..Format::default()
+            },
+            Format {
+                type_tags: true,
+                ..Format::default()
+            },
+        ];
+        for format in formats.iter() {
+            let bytes = format.to_bytes();
+            assert_eq!(Format::from_bytes(&bytes).unwrap(), *format);
+        }
+        assert_eq!(Format::default().to_bytes(), [0, 0]);
+        let all = Format {
+            version: EncodingVersion::V1,
+            domain_separation: true,
+            type_tags: true,
+        };
+        assert_eq!(all.to_bytes(), [1, 3]);
+
+        for bytes in [&[2u8, 0][..], &[0, 0x80], &[0], &[0, 0, 0]].iter() {
+            let err = Format::from_bytes(bytes).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -5,6 +5,7 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::borrow::Cow;
+use std::error::Error;
 use std::fmt;
 use std::io::{self, Read, Write};
 use std::mem;
@@ -18,7 +19,8 @@ use crate::annotations::ErasedAnnotation;
 use crate::compound::Compound;
 use crate::content::Content;
 use crate::debug_draw::{DebugDraw, DrawState};
-use crate::sink::Sink;
+use crate::format::Format;
+use crate::sink::{HashDomain, Sink};
 use crate::source::Source;
 use crate::store::Snapshot;
 
@@ -41,11 +43,38 @@ where
     H: ByteHash,
 {
     Leaf(C::Leaf),
-    Node(Rc<C>, C::Annotation, Option<H::Digest>),
+    // The hash of the node is cached along with the format it was computed in
+    Node(Rc<C>, C::Annotation, Option<(Format, H::Digest)>),
     Persisted(Snapshot<C, H>, C::Annotation),
+    // Known only by hash and annotation, with no way to load the node
+    Pruned(H::Digest, C::Annotation),
     None,
 }
 
+/// The error returned when accessing a pruned node, see `Handle::pruned`
+#[derive(Debug)]
+pub struct PrunedError;
+
+impl PrunedError {
+    /// Returns true if the error was caused by accessing a pruned node
+    pub fn matches(err: &io::Error) -> bool {
+        err.get_ref()
+            .map_or(false, |inner| inner.is::<PrunedError>())
+    }
+
+    fn new() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, PrunedError)
+    }
+}
+
+impl fmt::Display for PrunedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Access to pruned node")
+    }
+}
+
+impl Error for PrunedError {}
+
 #[derive(Debug, PartialEq, Eq)]
 /// Represents the type of the handle
 pub enum HandleType {
@@ -135,6 +164,9 @@ where
             HandleInner::Persisted(ref snap, ref ann) => {
                 HandleInner::Persisted(snap.clone(), ann.clone())
             }
+            HandleInner::Pruned(ref hash, ref ann) => {
+                HandleInner::Pruned(*hash, ann.clone())
+            }
             HandleInner::None => HandleInner::None,
         }
     }
@@ -152,12 +184,22 @@ where
                 sink.write_all(&[1])?;
                 leaf.persist(sink)
             }
-            HandleInner::Persisted(ref hash, ref mut ann) => {
+            HandleInner::Persisted(ref snap, ref mut ann) => {
+                let hash = snap.hash_with(sink.format())?;
+                sink.write_all(&[2])?;
+                sink.write_all(hash.as_ref())?;
+                ann.persist(sink)
+            }
+            // Encoded like a persisted node, to keep the hash of the parent
+            HandleInner::Pruned(ref hash, ref mut ann) => {
                 sink.write_all(&[2])?;
-                sink.write_all((**hash).as_ref())?;
+                sink.write_all(hash.as_ref())?;
                 ann.persist(sink)
             }
             HandleInner::Node(ref mut node, ref mut ann, ref mut cached) => {
+                // Child nodes are hashed as tree nodes, see `HashDomain`
+                let domain = HashDomain::Node;
+                let format = sink.format();
                 match sink.store() {
                     Some(store) => {
                         // We need to write the data to the backing store
@@ -165,16 +207,17 @@ where
                         // Create new sink sharing the store, either with the cached
                         // hash or post-hashing
                         let mut sub_sink = match *cached {
-                            Some(hash) => {
+                            Some((f, hash)) if f == format => {
                                 debug_assert!({
-                                    let mut sub_sink = Sink::new_dry();
+                                    let mut sub_sink =
+                                        Sink::new_dry(format, domain);
                                     Rc::make_mut(node)
                                         .persist(&mut sub_sink)?;
                                     sub_sink.fin()? == hash
                                 });
                                 Sink::new_cached(hash, store)
                             }
-                            None => Sink::new(store),
+                            _ => Sink::new(store, domain),
                         };
 
                         // Persist the node to the sub-sink
@@ -191,21 +234,23 @@ where
                     None => {
                         // No store, we're doing a dry run
                         let hash = match *cached {
-                            Some(hash) => {
+                            Some((f, hash)) if f == format => {
                                 debug_assert!({
-                                    let mut sub_sink = Sink::new_dry();
+                                    let mut sub_sink =
+                                        Sink::new_dry(format, domain);
                                     Rc::make_mut(node)
                                         .persist(&mut sub_sink)?;
                                     sub_sink.fin()? == hash
                                 });
                                 hash
                             }
-                            None => {
-                                let mut sub_sink = Sink::new_dry();
+                            _ => {
+                                let mut sub_sink =
+                                    Sink::new_dry(format, domain);
                                 Rc::make_mut(node).persist(&mut sub_sink)?;
                                 let hash = sub_sink.fin()?;
                                 // Update our hash cache
-                                *cached = Some(hash);
+                                *cached = Some((format, hash));
                                 hash
                             }
                         };
@@ -397,6 +442,12 @@ where
         Handle(HandleInner::Node(node, ann, None))
     }
 
+    /// Constructs a new pruned node Handle, from the hash and annotation of
+    /// the node
+    pub fn new_pruned(hash: H::Digest, ann: C::Annotation) -> Handle<C, H> {
+        Handle(HandleInner::Pruned(hash, ann))
+    }
+
     /// Constructs a new empty node Handle
     pub fn new_empty() -> Handle<C, H> {
         Handle(HandleInner::None)
@@ -458,17 +509,70 @@ where
         }
     }
 
-    pub(crate) fn node_hash(&mut self) -> Option<H::Digest> {
-        match self.0 {
+    pub(crate) fn node_hash(
+        &mut self,
+        format: Format,
+    ) -> io::Result<Option<H::Digest>> {
+        Ok(match self.0 {
             HandleInner::None => None,
             HandleInner::Leaf(_) => None,
             HandleInner::Node(ref mut n, ..) => {
-                Some(Rc::make_mut(n).root_hash())
+                Some(Rc::make_mut(n).root_hash_with(format)?)
             }
-            HandleInner::Persisted(ref hash, ..) => Some(*hash.hash()),
+            HandleInner::Persisted(ref snap, ..) => {
+                Some(snap.hash_with(format)?)
+            }
+            HandleInner::Pruned(ref hash, _) => Some(*hash),
+        })
+    }
+
+    /// Returns true if the Handle is pointing to a pruned node
+    pub fn is_pruned(&self) -> bool {
+        match self.0 {
+            HandleInner::Pruned(..) => true,
+            _ => false,
         }
     }
 
+    /// Returns a copy of the handle with a node pruned, that is known only
+    /// by its hash and annotation.
+    ///
+    /// Pruned nodes hash like the original, but accessing them fails with a
+    /// `PrunedError`, which allows working on sparse views of structures.
+    /// Nodes are pruned to their hash in the default `Format`.
+    ///
+    /// Panics if the node is persisted in a store of another format, see
+    /// `pruned_with`.
+    pub fn pruned(&mut self) -> Self {
+        self.pruned_with(Format::default())
+            .expect("Node persisted in another format")
+    }
+
+    /// Returns a copy of the handle with a node pruned to its hash in the
+    /// given format.
+    ///
+    /// Nodes persisted in a store are known by their hash in the format of
+    /// the store, asking for another format fails with `InvalidInput`.
+    pub fn pruned_with(&mut self, format: Format) -> io::Result<Self> {
+        Ok(match self.0 {
+            HandleInner::Node(ref mut n, ref ann, ref mut cached) => {
+                let hash = match *cached {
+                    Some((f, hash)) if f == format => hash,
+                    _ => {
+                        let hash = Rc::make_mut(n).root_hash_with(format)?;
+                        *cached = Some((format, hash));
+                        hash
+                    }
+                };
+                Handle(HandleInner::Pruned(hash, ann.clone()))
+            }
+            HandleInner::Persisted(ref snap, ref ann) => Handle(
+                HandleInner::Pruned(snap.hash_with(format)?, ann.clone()),
+            ),
+            _ => self.clone(),
+        })
+    }
+
     /// Return the annotation for the handle, unless None
     pub fn annotation(&self) -> Option<Cow<C::Annotation>> {
         match self.0 {
@@ -477,7 +581,8 @@ where
                 Some(Cow::Owned(C::Annotation::from(l)))
             }
             HandleInner::Node(_, ref ann, _)
-            | HandleInner::Persisted(_, ref ann) => Some(Cow::Borrowed(ann)),
+            | HandleInner::Persisted(_, ref ann)
+            | HandleInner::Pruned(_, ref ann) => Some(Cow::Borrowed(ann)),
         }
     }
 
@@ -493,6 +598,7 @@ where
                 let restored = snap.restore()?;
                 HandleRef::Node(Cached::Spilled(Box::new(restored)))
             }
+            HandleInner::Pruned(..) => return Err(PrunedError::new()),
         })
     }
 
@@ -520,6 +626,7 @@ where
                     unreachable!()
                 }
             }
+            HandleInner::Pruned(..) => Err(PrunedError::new()),
         }
     }
 }
@@ -0,0 +1,84 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::convert::TryInto;
+use std::hash::Hasher;
+
+use bytehash::{ByteHash, State};
+use sha2::Digest;
+
+use crate::store::HashName;
+
+// `Hasher::finish`, the first 8 bytes of the digest
+fn finish(digest: &[u8; 32]) -> u64 {
+    u64::from_le_bytes(digest[..8].try_into().expect("32 byte digest"))
+}
+
+macro_rules! digest_hash {
+    ($name:ident, $state:ident, $inner:ty, $hash_name:expr, $doc:expr) => {
+        #[doc = $doc]
+        #[derive(Clone, Debug)]
+        pub struct $name;
+
+        /// Hashing state
+        #[derive(Clone)]
+        pub struct $state($inner);
+
+        impl Hasher for $state {
+            fn finish(&self) -> u64 {
+                finish(&self.clone().fin())
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                Digest::update(&mut self.0, bytes)
+            }
+        }
+
+        impl State<[u8; 32]> for $state {
+            fn fin(self) -> [u8; 32] {
+                Digest::finalize(self.0).into()
+            }
+        }
+
+        impl ByteHash for $name {
+            type Digest = [u8; 32];
+            type State = $state;
+
+            fn state() -> Self::State {
+                $state(<$inner as Digest>::new())
+            }
+        }
+
+        impl HashName for $name {
+            const NAME: &'static str = $hash_name;
+        }
+    };
+}
+
+digest_hash!(
+    Sha256,
+    Sha256State,
+    sha2::Sha256,
+    "sha256",
+    "SHA-256, as specified in FIPS 180-4"
+);
+
+digest_hash!(
+    Keccak256,
+    Keccak256State,
+    sha3::Keccak256,
+    "keccak256",
+    "Keccak-256, the original Keccak submission as used by Ethereum, not \
+     the padding-incompatible SHA3-256"
+);
+
+digest_hash!(
+    Blake3,
+    Blake3State,
+    blake3::Hasher,
+    "blake3",
+    "BLAKE3, with 32 byte output"
+);
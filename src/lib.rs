@@ -14,44 +14,65 @@ pub mod tests;
 pub mod annotations;
 
 mod backend;
+mod blob;
 mod branch;
 mod compound;
 mod content;
 mod debug_draw;
 mod erased;
+mod float;
+mod format;
 mod handle;
+mod hashes;
 mod iter;
+mod link;
 mod map;
+mod numeric;
 mod proof;
 mod raw_branch;
 mod root;
 mod search;
+mod serded;
 mod sink;
 mod source;
 mod store;
+mod varint;
 
 pub use crate::annotations::{
     Annotation, Associative, Combine, ErasedAnnotation, Void,
 };
 pub use crate::backend::Backend;
+pub use crate::blob::{Blob, BlobReader, BlobWriter};
 pub use crate::branch::{Branch, BranchMut};
 pub use crate::compound::Compound;
 pub use crate::content::Content;
 pub use crate::debug_draw::{DebugDraw, DrawState};
-pub use crate::erased::{Erased, Query, Transaction};
+pub use crate::erased::{
+    CommitResult, Erased, Query, SharedErased, SharedTransaction, Transaction,
+    TypeRegistry, TypeTag,
+};
+pub use crate::float::StrictFloat;
+pub use crate::format::{EncodingVersion, Format};
 pub use crate::handle::{
     Handle, HandleMut, HandleMutLeaf, HandleMutNode, HandleMutNone, HandleRef,
-    HandleType,
+    HandleType, PrunedError,
+};
+pub use crate::hashes::{
+    Blake3, Blake3State, Keccak256, Keccak256State, Sha256, Sha256State,
 };
 pub use crate::iter::LeafIterable;
+pub use crate::link::Link;
 pub use crate::map::{ValIterable, ValPath, ValPathMut, ValRef, ValRefMut, KV};
-pub use crate::proof::Proof;
+pub use crate::numeric::Decimal;
+pub use crate::proof::{AnnotationProof, MultiProof, Proof, Witness};
 pub use crate::raw_branch::Level;
 pub use crate::root::Root;
 pub use crate::search::{Method, SearchResult};
-pub use crate::sink::Sink;
-pub use crate::source::Source;
-pub use crate::store::{Snapshot, Store};
+pub use crate::serded::Serded;
+pub use crate::sink::{HashDomain, Sink};
+pub use crate::source::{Limits, Source};
+pub use crate::store::{HashName, Snapshot, SnapshotId, Store};
+pub use crate::varint::Varint;
 
 // Re-export
 pub use bytehash::{Blake2b, ByteHash, State as ByteHashState};
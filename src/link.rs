@@ -0,0 +1,321 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use bytehash::ByteHash;
+use cache::Cached;
+
+use crate::content::Content;
+use crate::format::Format;
+use crate::handle::RcExt;
+use crate::sink::Sink;
+use crate::source::Source;
+use crate::store::Snapshot;
+
+enum LinkInner<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    // The hash is cached along with the format it was computed in
+    Memory(Rc<T>, Option<(Format, H::Digest)>),
+    Persisted(Snapshot<T, H>),
+}
+
+/// A typed reference to a sub-structure, usable as a field in any type
+/// implementing `Content`.
+///
+/// The value is either held in memory, or known only by its hash and loaded
+/// lazily from the store on first access. Persisting a `Link` persists the
+/// value as a separate node, and writes only its hash in the parent.
+pub struct Link<T, H>(LinkInner<T, H>)
+where
+    T: Content<H>,
+    H: ByteHash;
+
+impl<T, H> Clone for Link<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    fn clone(&self) -> Self {
+        Link(match self.0 {
+            LinkInner::Memory(ref t, ref cached) => {
+                LinkInner::Memory(t.clone(), *cached)
+            }
+            LinkInner::Persisted(ref snap) => {
+                LinkInner::Persisted(snap.clone())
+            }
+        })
+    }
+}
+
+impl<T, H> Default for Link<T, H>
+where
+    T: Content<H> + Default,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        Link::new(T::default())
+    }
+}
+
+impl<T, H> Link<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    /// Constructs a new in-memory `Link`
+    pub fn new(t: T) -> Self {
+        Link(LinkInner::Memory(Rc::new(t), None))
+    }
+
+    /// Returns true if the value is held in memory
+    pub fn is_loaded(&self) -> bool {
+        match self.0 {
+            LinkInner::Memory(..) => true,
+            LinkInner::Persisted(_) => false,
+        }
+    }
+
+    /// Returns a reference to the value, restoring it from the store if it
+    /// is not in memory. The restored value is not kept, use `load` for that.
+    pub fn get(&self) -> io::Result<Cached<'_, T>> {
+        Ok(match self.0 {
+            LinkInner::Memory(ref t, _) => Cached::Borrowed(t.as_ref()),
+            LinkInner::Persisted(ref snap) => {
+                Cached::Spilled(Box::new(snap.restore()?))
+            }
+        })
+    }
+
+    /// Loads the value into memory, if neccesary, and returns a reference
+    /// to it
+    pub fn load(&mut self) -> io::Result<&T> {
+        if let LinkInner::Persisted(ref snap) = self.0 {
+            let restored = snap.restore()?;
+            let cached = Some((snap.format(), *snap.hash()));
+            self.0 = LinkInner::Memory(Rc::new(restored), cached);
+        }
+        match self.0 {
+            LinkInner::Memory(ref t, _) => Ok(t),
+            LinkInner::Persisted(_) => unreachable!(),
+        }
+    }
+
+    /// Loads the value into memory, if neccesary, and returns a mutable
+    /// reference to it
+    pub fn get_mut(&mut self) -> io::Result<&mut T> {
+        self.load()?;
+        match self.0 {
+            LinkInner::Memory(ref mut t, ref mut cached) => {
+                // clear cached hash
+                *cached = None;
+                Ok(Rc::make_mut(t))
+            }
+            LinkInner::Persisted(_) => unreachable!(),
+        }
+    }
+
+    /// Unwraps the `Link`, restoring the value from the store if neccesary
+    pub fn into_inner(self) -> io::Result<T> {
+        match self.0 {
+            LinkInner::Memory(t, _) => Ok(t.unwrap_or_clone()),
+            LinkInner::Persisted(snap) => snap.restore(),
+        }
+    }
+
+    /// Returns the hash of the linked value, in the default `Format`.
+    /// This does not write anything to disk, the hash is computed and cached
+    pub fn hash(&mut self) -> io::Result<H::Digest> {
+        self.hash_with(Format::default())
+    }
+
+    /// Returns the hash of the linked value in the given `Format`.
+    ///
+    /// Values persisted in a store are known by their hash in the format of
+    /// the store, asking for another format fails with `InvalidInput`.
+    pub fn hash_with(&mut self, format: Format) -> io::Result<H::Digest> {
+        match self.0 {
+            LinkInner::Memory(ref mut t, ref mut cached) => match *cached {
+                Some((f, hash)) if f == format => Ok(hash),
+                _ => {
+                    let mut sink = Sink::new_dry(format, T::hash_domain());
+                    Rc::make_mut(t).persist(&mut sink)?;
+                    let hash = sink.fin()?;
+                    *cached = Some((format, hash));
+                    Ok(hash)
+                }
+            },
+            LinkInner::Persisted(ref snap) => snap.hash_with(format),
+        }
+    }
+}
+
+impl<T, H> Content<H> for Link<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        let format = sink.format();
+        if let LinkInner::Memory(ref mut t, ref mut cached) = self.0 {
+            if let Some(store) = sink.store() {
+                // We need to write the value to the backing store
+
+                // Create new sink sharing the store, either with the cached
+                // hash or post-hashing
+                let mut sub_sink = match *cached {
+                    Some((f, hash)) if f == format => {
+                        debug_assert!({
+                            let mut sub_sink =
+                                Sink::new_dry(format, T::hash_domain());
+                            Rc::make_mut(t).persist(&mut sub_sink)?;
+                            sub_sink.fin()? == hash
+                        });
+                        Sink::new_cached(hash, store)
+                    }
+                    _ => Sink::new(store, T::hash_domain()),
+                };
+
+                Rc::make_mut(t).persist(&mut sub_sink)?;
+                let hash = sub_sink.fin()?;
+
+                // update the link to a persisted reference
+                self.0 = LinkInner::Persisted(Snapshot::new(hash, store));
+            }
+        }
+        // In a dry run, the hash is computed and cached without persisting
+        let hash = self.hash_with(format)?;
+        sink.write_all(hash.as_ref())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut hash = H::Digest::default();
+        source.read_exact(hash.as_mut())?;
+        Ok(Link(LinkInner::Persisted(Snapshot::new(
+            hash,
+            source.store(),
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{EncodingVersion, Store};
+    use bytehash::Blake2b;
+
+    #[derive(Clone)]
+    struct State<H: ByteHash> {
+        counter: u64,
+        history: Link<Vec<u64>, H>,
+    }
+
+    impl<H: ByteHash> Content<H> for State<H> {
+        fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+            self.counter.persist(sink)?;
+            self.history.persist(sink)
+        }
+
+        fn restore(source: &mut Source<H>) -> io::Result<Self> {
+            Ok(State {
+                counter: u64::restore(source)?,
+                history: Link::restore(source)?,
+            })
+        }
+    }
+
+    fn dry_hash<T: Content<H>, H: ByteHash>(t: &mut T) -> H::Digest {
+        let mut sink = Sink::new_dry(Format::default(), T::hash_domain());
+        t.persist(&mut sink).unwrap();
+        sink.fin().unwrap()
+    }
+
+    #[test]
+    fn lazy_restore() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut state = State {
+            counter: 3,
+            history: Link::new(vec![1, 2, 3]),
+        };
+
+        let dry = dry_hash(&mut state);
+        let snapshot = store.persist(&mut state).unwrap();
+        assert_eq!(&dry, snapshot.hash());
+
+        let mut restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.counter, 3);
+        assert!(!restored.history.is_loaded());
+
+        assert_eq!(*restored.history.get().unwrap(), vec![1, 2, 3]);
+        assert!(!restored.history.is_loaded());
+
+        assert_eq!(*restored.history.load().unwrap(), vec![1, 2, 3]);
+        assert!(restored.history.is_loaded());
+
+        // Loading does not change the hash
+        assert_eq!(&dry_hash(&mut restored), snapshot.hash());
+    }
+
+    #[test]
+    fn persisted_format() {
+        let format = Format {
+            version: EncodingVersion::V1,
+            ..Format::default()
+        };
+        let store = Store::<Blake2b>::ephemeral_with_format(format);
+
+        let mut history = Link::new(vec![1, 2, 3]);
+        let hash = history.hash_with(format).unwrap();
+        let mut state = State {
+            counter: 0,
+            history,
+        };
+        let snapshot = store.persist(&mut state).unwrap();
+
+        let mut restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.history.hash_with(format).unwrap(), hash);
+        let err = restored.history.hash().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // once loaded, the value can be hashed in any format
+        restored.history.load().unwrap();
+        assert!(restored.history.hash().is_ok());
+    }
+
+    #[test]
+    fn modify_restored() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        let mut state = State {
+            counter: 0,
+            history: Link::default(),
+        };
+
+        let mut snapshot = store.persist(&mut state).unwrap();
+
+        for i in 0..8 {
+            let mut restored = store.restore(&snapshot).unwrap();
+            restored.counter += 1;
+            restored.history.get_mut().unwrap().push(i);
+            let new_snapshot = store.persist(&mut restored).unwrap();
+            assert!(new_snapshot.hash() != snapshot.hash());
+            snapshot = new_snapshot;
+        }
+
+        let restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.counter, 8);
+        assert_eq!(
+            restored.history.into_inner().unwrap(),
+            (0..8).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,269 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+
+use bytehash::ByteHash;
+use num::bigint::Sign;
+use num::{BigInt, BigUint, One, Signed, Zero};
+
+use crate::content::{read_raw_len, write_len, Content};
+use crate::sink::Sink;
+use crate::source::Source;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Big-endian magnitude, without leading zeroes. Zero is the empty string.
+fn write_magnitude<H: ByteHash>(
+    n: &BigUint,
+    sink: &mut Sink<H>,
+) -> io::Result<()> {
+    if n.is_zero() {
+        return write_len(sink, 0);
+    }
+    let bytes = n.to_bytes_be();
+    write_len(sink, bytes.len())?;
+    sink.write_all(&bytes)
+}
+
+fn read_magnitude<H: ByteHash>(source: &mut Source<H>) -> io::Result<BigUint> {
+    let len = read_raw_len(source)?;
+    let len = source.check_len(len)?;
+    let mut bytes = vec![];
+    source.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Truncated big integer encoding",
+        ));
+    }
+    if bytes.first() == Some(&0) {
+        return Err(invalid("Non-minimal big integer encoding"));
+    }
+    Ok(BigUint::from_bytes_be(&bytes))
+}
+
+impl<H: ByteHash> Content<H> for BigUint {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        write_magnitude(self, sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        read_magnitude(source)
+    }
+}
+
+// A sign byte, 1 for negative numbers, followed by the magnitude
+impl<H: ByteHash> Content<H> for BigInt {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        let mut negative = self.is_negative();
+        negative.persist(sink)?;
+        write_magnitude(&self.abs().to_biguint().expect("positive"), sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let negative = bool::restore(source)?;
+        let magnitude = read_magnitude(source)?;
+        match (negative, magnitude.is_zero()) {
+            (true, true) => Err(invalid("Negative zero big integer")),
+            (true, false) => Ok(BigInt::from_biguint(Sign::Minus, magnitude)),
+            (false, _) => Ok(BigInt::from_biguint(Sign::Plus, magnitude)),
+        }
+    }
+}
+
+/// An arbitrary precision fixed-point decimal number, with `SCALE` digits
+/// after the decimal point.
+///
+/// Stored as an integer mantissa, so every value has a single encoding.
+/// Multiplication rounds towards zero.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal<const SCALE: u32> {
+    mantissa: BigInt,
+}
+
+impl<const SCALE: u32> Decimal<SCALE> {
+    /// Creates a decimal from its mantissa, the value times `10^SCALE`
+    pub fn from_mantissa<I: Into<BigInt>>(mantissa: I) -> Self {
+        Decimal {
+            mantissa: mantissa.into(),
+        }
+    }
+
+    /// Returns the mantissa, the value times `10^SCALE`
+    pub fn mantissa(&self) -> &BigInt {
+        &self.mantissa
+    }
+
+    fn unit() -> BigInt {
+        num::pow(BigInt::from(10u8), SCALE as usize)
+    }
+}
+
+impl<const SCALE: u32> From<BigInt> for Decimal<SCALE> {
+    fn from(n: BigInt) -> Self {
+        Decimal::from_mantissa(n * Self::unit())
+    }
+}
+
+impl<const SCALE: u32> From<i64> for Decimal<SCALE> {
+    fn from(n: i64) -> Self {
+        BigInt::from(n).into()
+    }
+}
+
+impl<const SCALE: u32> fmt::Display for Decimal<SCALE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.abs().to_str_radix(10);
+        let scale = SCALE as usize;
+        let digits = if digits.len() <= scale {
+            format!("{:0>width$}", digits, width = scale + 1)
+        } else {
+            digits
+        };
+        if self.mantissa.is_negative() {
+            write!(f, "-")?;
+        }
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        if scale == 0 {
+            write!(f, "{}", int)
+        } else {
+            write!(f, "{}.{}", int, frac)
+        }
+    }
+}
+
+impl<const SCALE: u32> Add for Decimal<SCALE> {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Decimal::from_mantissa(self.mantissa + other.mantissa)
+    }
+}
+
+impl<const SCALE: u32> Sub for Decimal<SCALE> {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Decimal::from_mantissa(self.mantissa - other.mantissa)
+    }
+}
+
+impl<const SCALE: u32> Mul for Decimal<SCALE> {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Decimal::from_mantissa(self.mantissa * other.mantissa / Self::unit())
+    }
+}
+
+impl<const SCALE: u32> AddAssign for Decimal<SCALE> {
+    fn add_assign(&mut self, other: Self) {
+        self.mantissa += other.mantissa
+    }
+}
+
+impl<const SCALE: u32> SubAssign for Decimal<SCALE> {
+    fn sub_assign(&mut self, other: Self) {
+        self.mantissa -= other.mantissa
+    }
+}
+
+impl<const SCALE: u32> Zero for Decimal<SCALE> {
+    fn zero() -> Self {
+        Decimal::from_mantissa(BigInt::zero())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+}
+
+impl<const SCALE: u32> One for Decimal<SCALE> {
+    fn one() -> Self {
+        Decimal::from_mantissa(Self::unit())
+    }
+}
+
+impl<H: ByteHash, const SCALE: u32> Content<H> for Decimal<SCALE> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.mantissa.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Decimal::from_mantissa(BigInt::restore(source)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::{Cardinality, Counter};
+    use crate::Store;
+    use bytehash::Blake2b;
+
+    fn roundtrip<T: Content<Blake2b> + PartialEq + fmt::Debug>(mut t: T) {
+        let store = Store::<Blake2b>::ephemeral();
+        let snapshot = store.persist(&mut t).unwrap();
+        assert_eq!(store.restore(&snapshot).unwrap(), t);
+    }
+
+    fn is_counter<T: Counter>() {}
+
+    #[test]
+    fn big_integers() {
+        let large = BigUint::from(u128::max_value()) * 3u8;
+        roundtrip(BigUint::zero());
+        roundtrip(large.clone());
+        roundtrip(BigInt::zero());
+        roundtrip(BigInt::from_biguint(Sign::Minus, large.clone()));
+        roundtrip(BigInt::from_biguint(Sign::Plus, large));
+
+        is_counter::<BigUint>();
+        is_counter::<BigInt>();
+        let _: Cardinality<BigUint> = Cardinality::from(&());
+    }
+
+    #[test]
+    fn reject_non_canonical() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        // leading zero byte in the magnitude
+        let snapshot = store.persist(&mut vec![0u8, 1]).unwrap();
+        assert!(store
+            .snapshot_from_digest::<BigUint>(snapshot.hash())
+            .is_err());
+
+        // negative zero
+        let snapshot = store.persist(&mut (true, Vec::<u8>::new())).unwrap();
+        assert!(store
+            .snapshot_from_digest::<BigInt>(snapshot.hash())
+            .is_err());
+    }
+
+    #[test]
+    fn decimals() {
+        type Price = Decimal<4>;
+
+        let price = Price::from_mantissa(-12_345);
+        assert_eq!(price.to_string(), "-1.2345");
+        assert_eq!(Price::from_mantissa(5).to_string(), "0.0005");
+        assert_eq!(Price::from(3).to_string(), "3.0000");
+        assert_eq!(Decimal::<0>::from(-3).to_string(), "-3");
+
+        assert_eq!(
+            Price::from(2) * Price::from_mantissa(12_345),
+            Price::from_mantissa(24_690)
+        );
+        assert_eq!(Price::one() + Price::one(), Price::from(2));
+
+        roundtrip(price);
+        roundtrip(Price::zero());
+        is_counter::<Price>();
+    }
+}
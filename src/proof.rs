@@ -4,76 +4,186 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::io;
 use std::marker::PhantomData;
 
-use crate::branch::BranchMut;
+use crate::branch::{Branch, BranchMut};
 use crate::compound::Compound;
-use crate::handle::Handle;
+use crate::content::{read_raw_len, write_len, Content};
+use crate::format::Format;
+use crate::handle::{Handle, HandleRef, HandleType};
 use crate::raw_branch::Level;
+use crate::search::{Method, SearchResult};
+use crate::sink::Sink;
+use crate::source::Source;
 use bytehash::ByteHash;
 
+// Prunes the children of the node, leaving their hashes in the format and
+// their annotations
+fn prune_children<C: Compound<H>, H: ByteHash>(
+    node: &mut C,
+    format: Format,
+) -> io::Result<()> {
+    for child in node.children_mut() {
+        *child = child.pruned_with(format)?;
+    }
+    Ok(())
+}
+
+// Proofs hold hashes in the format they were created in, and can only be
+// written to a sink of that format
+fn check_format<H: ByteHash>(sink: &Sink<H>, format: Format) -> io::Result<()> {
+    if sink.format() == format {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Proof format mismatch",
+        ))
+    }
+}
+
+// A level of the proof, holding a copy of the node with its children nodes
+// pruned
 struct ProofLevel<C: Compound<H>, H: ByteHash> {
     ofs: usize,
     node: C,
     _marker: PhantomData<H>,
 }
 
-impl<C, H> ProofLevel<C, H>
+impl<C, H> Clone for ProofLevel<C, H>
 where
     C: Compound<H>,
     H: ByteHash,
 {
-    fn root_hash(&mut self) -> H::Digest {
-        self.node.root_hash()
+    fn clone(&self) -> Self {
+        ProofLevel {
+            ofs: self.ofs,
+            node: self.node.clone(),
+            _marker: PhantomData,
+        }
     }
+}
 
-    fn children(&self) -> &[Handle<C, H>] {
-        self.node.children()
+impl<C, H> ProofLevel<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn new(level: &mut Level<C, H>, format: Format) -> io::Result<Self> {
+        // Make sure we compute and cache the hashes along the path
+        level.root_hash_with(format)?;
+        Self::from_node((*level).clone(), level.offset(), format)
     }
 
-    fn children_mut(&mut self) -> &mut [Handle<C, H>] {
-        self.node.children_mut()
+    fn from_node(mut node: C, ofs: usize, format: Format) -> io::Result<Self> {
+        assert!(ofs < node.children().len(), "Offset out of bounds");
+        prune_children(&mut node, format)?;
+        Ok(ProofLevel {
+            ofs,
+            node,
+            _marker: PhantomData,
+        })
     }
 }
 
-impl<C, H> From<&mut Level<'_, C, H>> for ProofLevel<C, H>
+/// A merkle proof that a certain leaf exists in a compound collection.
+///
+/// The proof holds, per level of the tree, the offset taken and the encoding
+/// of the node, that is the digests and annotations of its children and any
+/// leaves stored inline. It can be persisted or transmitted as `Content`.
+///
+/// Proofs are created in the default `Format` unless given another one, and
+/// restored in the format of the store.
+pub struct Proof<C: Compound<H>, H: ByteHash>(Vec<ProofLevel<C, H>>, Format);
+
+impl<C, H> Clone for Proof<C, H>
 where
     C: Compound<H>,
     H: ByteHash,
 {
-    fn from(level: &mut Level<C, H>) -> Self {
-        // Make sure we compute and cache the hashes along the path
-        let _ = level.root_hash();
-        ProofLevel {
-            ofs: level.offset(),
-            node: (*level).clone(),
-            _marker: PhantomData,
-        }
+    fn clone(&self) -> Self {
+        Proof(self.0.clone(), self.1)
     }
 }
 
-/// A merkle proof that a certain leaf exists in a compound collection
-pub struct Proof<C: Compound<H>, H: ByteHash>(Vec<ProofLevel<C, H>>);
-
 impl<C, H> Proof<C, H>
 where
     C: Compound<H>,
     H: ByteHash,
-    H::Digest: std::fmt::Debug,
 {
     /// Creates a new proof from a branch
+    ///
+    /// Panics if the branch passes through nodes persisted in a store of
+    /// another format than the default, see `new_with_format`.
     pub fn new(from: &mut BranchMut<C, H>) -> Self {
+        Self::new_with_format(from, Format::default())
+            .expect("Nodes persisted in another format")
+    }
+
+    /// Creates a new proof from a branch, hashing in the given format.
+    ///
+    /// Fails with `InvalidInput` if the branch passes through nodes persisted
+    /// in a store of another format.
+    pub fn new_with_format(
+        from: &mut BranchMut<C, H>,
+        format: Format,
+    ) -> io::Result<Self> {
         let mut branch = vec![];
 
         for level in from.levels_mut() {
-            branch.push(ProofLevel::from(level))
+            branch.push(ProofLevel::new(level, format)?)
         }
-        Proof(branch)
+        Ok(Proof(branch, format))
+    }
+
+    /// Creates a proof from a path of nodes starting at the root, and the
+    /// offset taken in each node.
+    ///
+    /// Used to prove facts other than membership, such as the absence of a
+    /// key, by the structure at the end of the path. Panics if an offset is
+    /// out of bounds, or if the path holds nodes persisted in a store of
+    /// another format than the default.
+    pub fn from_path<I>(path: I) -> Self
+    where
+        I: IntoIterator<Item = (C, usize)>,
+    {
+        Self::from_path_with_format(path, Format::default())
+            .expect("Nodes persisted in another format")
+    }
+
+    /// Creates a proof from a path of nodes, hashing in the given format.
+    ///
+    /// Fails with `InvalidInput` if the path holds nodes persisted in a store
+    /// of another format.
+    pub fn from_path_with_format<I>(path: I, format: Format) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = (C, usize)>,
+    {
+        let levels = path
+            .into_iter()
+            .map(|(node, ofs)| ProofLevel::from_node(node, ofs, format))
+            .collect::<io::Result<_>>()?;
+        Ok(Proof(levels, format))
+    }
+
+    /// Returns the format the proof is hashed in
+    pub fn format(&self) -> Format {
+        self.1
+    }
+
+    /// Returns the nodes along the path of the proof, and the offset taken
+    /// in each, starting at the root.
+    ///
+    /// Children of the returned nodes are known only by their hash and
+    /// annotation.
+    pub fn levels(&self) -> impl Iterator<Item = (&C, usize)> {
+        self.0.iter().map(|level| (&level.node, level.ofs))
     }
 
     fn get_leaf(&self) -> Option<&C::Leaf> {
         if let Some(level) = self.0.last() {
-            level.children()[level.ofs].leaf()
+            level.node.children()[level.ofs].leaf()
         } else {
             None
         }
@@ -82,32 +192,626 @@ where
     /// Proves the inclusion of the element and returns a reference to it
     /// or None if the proof is invalid.
     pub fn prove_member(&mut self, against: &mut C) -> Option<&C::Leaf> {
-        // verify that all the hashes are correct bottom up
+        // Verify against the structure we want to prove with, hashing its
+        // root as a tree node like the levels of the proof
+        let root = against.root_hash_with(self.1).ok()?;
+        self.verify(&root)
+    }
+
+    /// Verifies the proof against the root digest of a structure, returning
+    /// a reference to the proven element, or None if the proof is invalid.
+    pub fn verify(&self, root: &H::Digest) -> Option<&C::Leaf> {
+        if self.verify_path(root) {
+            self.get_leaf()
+        } else {
+            None
+        }
+    }
+
+    /// Verifies that the path of the proof leads from the root digest down
+    /// to the last node of the proof.
+    pub fn verify_path(&self, root: &H::Digest) -> bool {
+        // verify that all the hashes are correct bottom up, levels are
+        // hashed as nodes, see `HashDomain`
 
         let mut previous = None;
 
-        for level in self.0.iter_mut().rev() {
+        for level in self.0.iter().rev() {
+            // hash a copy, leaving the proof untouched
+            let mut node = level.node.clone();
             if let Some(prev) = previous {
-                let ofs = level.ofs;
-                if let Some(node_hash) = level.children_mut()[ofs].node_hash() {
-                    if node_hash != prev {
+                match node.children_mut()[level.ofs].node_hash(self.1) {
+                    Ok(Some(node_hash)) if node_hash == prev => (),
+                    _ => return false,
+                }
+            }
+            match node.root_hash_with(self.1) {
+                Ok(hash) => previous = Some(hash),
+                Err(_) => return false,
+            }
+        }
+        previous.as_ref() == Some(root)
+    }
+}
+
+impl<C, H> Content<H> for Proof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        check_format(sink, self.1)?;
+        write_len(sink, self.0.len())?;
+        for level in self.0.iter_mut() {
+            write_len(sink, level.ofs)?;
+            // Children are pruned, so only their hashes are written
+            level.node.persist(sink)?;
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let len = read_raw_len(source)?;
+        let len = source.check_len(len)?;
+        let mut levels = vec![];
+        for _ in 0..len {
+            let ofs = read_raw_len(source)? as usize;
+            let mut node = source.nested(C::restore)?;
+            prune_children(&mut node, source.format())?;
+            if ofs >= node.children().len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid Proof encoding",
+                ));
+            }
+            levels.push(ProofLevel {
+                ofs,
+                node,
+                _marker: PhantomData,
+            });
+        }
+        Ok(Proof(levels, source.format()))
+    }
+}
+
+/// A proof of the annotation of a compound collection, such as the number
+/// of leaves it holds.
+///
+/// Holds only the root node, with its children pruned to their hashes and
+/// annotations, which commits to the annotation of the whole structure.
+pub struct AnnotationProof<C: Compound<H>, H: ByteHash> {
+    node: C,
+    format: Format,
+    _marker: PhantomData<H>,
+}
+
+impl<C, H> Clone for AnnotationProof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn clone(&self) -> Self {
+        AnnotationProof {
+            node: self.node.clone(),
+            format: self.format,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, H> AnnotationProof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// Creates a proof of the annotation of the structure
+    ///
+    /// Panics if the root has children persisted in a store of another
+    /// format than the default, see `new_with_format`.
+    pub fn new(root: &mut C) -> Self {
+        Self::new_with_format(root, Format::default())
+            .expect("Nodes persisted in another format")
+    }
+
+    /// Creates a proof of the annotation of the structure, hashing in the
+    /// given format.
+    ///
+    /// Fails with `InvalidInput` if the root has children persisted in a
+    /// store of another format.
+    pub fn new_with_format(root: &mut C, format: Format) -> io::Result<Self> {
+        // Make sure we compute and cache the hashes of the children
+        root.root_hash_with(format)?;
+
+        let mut node = root.clone();
+        prune_children(&mut node, format)?;
+        Ok(AnnotationProof {
+            node,
+            format,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Verifies the proof against the root digest of a structure, returning
+    /// the annotation of the structure, or None if the proof is invalid or
+    /// the structure is empty.
+    pub fn verify(&self, root: &H::Digest) -> Option<C::Annotation> {
+        if self.node.clone().root_hash_with(self.format).ok()? == *root {
+            self.node.annotation()
+        } else {
+            None
+        }
+    }
+}
+
+impl<C, H> Content<H> for AnnotationProof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        check_format(sink, self.format)?;
+        self.node.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut node = C::restore(source)?;
+        prune_children(&mut node, source.format())?;
+        Ok(AnnotationProof {
+            node,
+            format: source.format(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+// A step of the walk through the nodes along a set of paths
+enum Step {
+    // Descend into the child at the offset of the current node
+    Down(usize),
+    // Return to the parent of the current node
+    Up,
+    // The current node holds the leaf of the path at this index
+    Holds(usize),
+}
+
+// Walks the nodes along the paths in pre-order, children in order of their
+// offsets, visiting nodes shared between paths once. The walk ends at the
+// root.
+fn walk(paths: &[Vec<usize>]) -> Vec<Step> {
+    let mut order: Vec<usize> = (0..paths.len()).collect();
+    order.sort_by(|a, b| paths[*a].cmp(&paths[*b]));
+
+    let mut steps = vec![];
+    // offsets from the root to the current node
+    let mut current: &[usize] = &[];
+    for n in order {
+        let path = &paths[n];
+        let nodes = &path[..path.len().saturating_sub(1)];
+        let shared = current
+            .iter()
+            .zip(nodes)
+            .take_while(|(a, b)| a == b)
+            .count();
+        steps.extend((shared..current.len()).map(|_| Step::Up));
+        steps.extend(nodes[shared..].iter().map(|ofs| Step::Down(*ofs)));
+        steps.push(Step::Holds(n));
+        current = nodes;
+    }
+    steps.extend(current.iter().map(|_| Step::Up));
+    steps
+}
+
+// Collects the root and the nodes below it along the paths, in pre-order,
+// with their children pruned
+fn collect<C, H>(
+    root: &C,
+    paths: &[Vec<usize>],
+    format: Format,
+) -> io::Result<Vec<C>>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    let mut pruned = root.clone();
+    prune_children(&mut pruned, format)?;
+    let mut nodes = vec![pruned];
+
+    let mut stack = vec![root.clone()];
+    for step in walk(paths) {
+        match step {
+            Step::Down(ofs) => {
+                let parent = stack.last().expect("walk ends at the root");
+                let child = match parent.children()[ofs].inner()? {
+                    HandleRef::Node(child) => (*child).clone(),
+                    _ => unreachable!("Path passes through node"),
+                };
+                let mut pruned = child.clone();
+                prune_children(&mut pruned, format)?;
+                nodes.push(pruned);
+                stack.push(child);
+            }
+            Step::Up => {
+                stack.pop();
+            }
+            Step::Holds(_) => (),
+        }
+    }
+    Ok(nodes)
+}
+
+fn write_paths_and_nodes<C, H>(
+    paths: &[Vec<usize>],
+    nodes: &mut [C],
+    sink: &mut Sink<H>,
+) -> io::Result<()>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    write_len(sink, paths.len())?;
+    for path in paths {
+        write_len(sink, path.len())?;
+        for ofs in path {
+            write_len(sink, *ofs)?;
+        }
+    }
+    write_len(sink, nodes.len())?;
+    for node in nodes.iter_mut() {
+        // Children are pruned, so only their hashes are written
+        node.persist(sink)?;
+    }
+    Ok(())
+}
+
+fn read_paths_and_nodes<C, H>(
+    source: &mut Source<H>,
+    error: &'static str,
+) -> io::Result<(Vec<Vec<usize>>, Vec<C>)>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    let len = read_raw_len(source)?;
+    let len = source.check_len(len)?;
+    let mut paths = vec![];
+    for _ in 0..len {
+        let path_len = read_raw_len(source)?;
+        let path_len = source.check_len(path_len)?;
+        // paths deeper than the nesting limit are rejected up front
+        if path_len == 0 || path_len > source.limits().max_depth {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        let mut path = vec![];
+        for _ in 0..path_len {
+            path.push(read_raw_len(source)? as usize);
+        }
+        paths.push(path);
+    }
+    let len = read_raw_len(source)?;
+    let len = source.check_len(len)?;
+    let mut nodes = vec![];
+    for _ in 0..len {
+        let mut node = source.nested(C::restore)?;
+        prune_children(&mut node, source.format())?;
+        nodes.push(node);
+    }
+    Ok((paths, nodes))
+}
+
+/// A merkle proof that several leaves exist in a compound collection.
+///
+/// The nodes along the paths to the leaves are included once, no matter how
+/// many of the paths share them.
+pub struct MultiProof<C: Compound<H>, H: ByteHash> {
+    // offsets from the root to each leaf
+    paths: Vec<Vec<usize>>,
+    // the nodes in pre-order, with their children pruned
+    nodes: Vec<C>,
+    format: Format,
+    _marker: PhantomData<H>,
+}
+
+impl<C, H> Clone for MultiProof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn clone(&self) -> Self {
+        MultiProof {
+            paths: self.paths.clone(),
+            nodes: self.nodes.clone(),
+            format: self.format,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, H> MultiProof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// Creates a proof of the leaves found by each of the search methods,
+    /// or None if any of the searches comes up empty
+    pub fn new<I, M>(root: &mut C, methods: I) -> io::Result<Option<Self>>
+    where
+        I: IntoIterator<Item = M>,
+        M: Method<C, H>,
+    {
+        Self::new_with_format(root, methods, Format::default())
+    }
+
+    /// Creates a proof of the leaves found by each of the search methods,
+    /// hashing in the given format
+    pub fn new_with_format<I, M>(
+        root: &mut C,
+        methods: I,
+        format: Format,
+    ) -> io::Result<Option<Self>>
+    where
+        I: IntoIterator<Item = M>,
+        M: Method<C, H>,
+    {
+        // Make sure we compute and cache the hashes in the tree
+        root.root_hash_with(format)?;
+
+        let mut paths = vec![];
+        for mut method in methods {
+            match Branch::new(&*root, &mut method)? {
+                Some(branch) => paths
+                    .push(branch.levels().iter().map(Level::offset).collect()),
+                None => return Ok(None),
+            }
+        }
+
+        let nodes = collect(root, &paths, format)?;
+        Ok(Some(MultiProof {
+            paths,
+            nodes,
+            format,
+            _marker: PhantomData,
+        }))
+    }
+
+    /// Verifies the proof against the root digest of a structure, returning
+    /// references to the proven elements in the order of the searches, or
+    /// None if the proof is invalid.
+    pub fn verify(&self, root: &H::Digest) -> Option<Vec<&C::Leaf>> {
+        let mut nodes = self.nodes.iter().enumerate();
+        let mut holders = vec![0; self.paths.len()];
+
+        // The nodes on the way down, with their index and the offset they
+        // were reached by. Each node is hashed when the walk leaves it, and
+        // checked against the hash in its parent.
+        let (_, first) = nodes.next()?;
+        let mut stack = vec![(0, 0, first.clone())];
+        for step in walk(&self.paths) {
+            match step {
+                Step::Down(ofs) => {
+                    let (index, node) = nodes.next()?;
+                    // hash a copy, leaving the proof untouched
+                    stack.push((index, ofs, node.clone()));
+                }
+                Step::Up => {
+                    let (_, ofs, mut node) = stack.pop()?;
+                    let (_, _, parent) = stack.last_mut()?;
+                    let expected = parent
+                        .children_mut()
+                        .get_mut(ofs)?
+                        .node_hash(self.format)
+                        .ok()??;
+                    if node.root_hash_with(self.format).ok()? != expected {
                         return None;
                     }
-                } else {
-                    return None;
                 }
+                Step::Holds(n) => holders[n] = stack.last()?.0,
             }
-            previous = Some(level.root_hash());
-        }
-        if let Some(root) = previous {
-            // Verify against the structure we want to prove with
-            if root == against.root_hash() {
-                self.get_leaf()
-            } else {
-                None
+        }
+        let (_, _, mut top) = stack.pop()?;
+        if nodes.next().is_some()
+            || top.root_hash_with(self.format).ok()? != *root
+        {
+            return None;
+        }
+
+        self.paths
+            .iter()
+            .zip(holders)
+            .map(|(path, holder)| {
+                let ofs = *path.last()?;
+                self.nodes[holder].children().get(ofs)?.leaf()
+            })
+            .collect()
+    }
+}
+
+impl<C, H> Content<H> for MultiProof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        check_format(sink, self.format)?;
+        write_paths_and_nodes(&self.paths, &mut self.nodes, sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let (paths, nodes) =
+            read_paths_and_nodes(source, "Invalid MultiProof encoding")?;
+        Ok(MultiProof {
+            paths,
+            nodes,
+            format: source.format(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// A witness of an operation on a compound collection, such as an insert,
+/// holding the nodes the operation touches.
+///
+/// The operation can be replayed on the partial structure rebuilt from the
+/// witness, to check that it takes the structure from one root digest to
+/// another, without access to the rest of the structure.
+pub struct Witness<C: Compound<H>, H: ByteHash> {
+    // offsets selected in each node from the root, the last one being the
+    // number of children if the search selected none
+    paths: Vec<Vec<usize>>,
+    // the nodes in pre-order, with their children pruned
+    nodes: Vec<C>,
+    format: Format,
+    _marker: PhantomData<H>,
+}
+
+impl<C, H> Clone for Witness<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn clone(&self) -> Self {
+        Witness {
+            paths: self.paths.clone(),
+            nodes: self.nodes.clone(),
+            format: self.format,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, H> Witness<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// Creates a witness of the nodes visited by each of the search methods,
+    /// descending into the child selected in each node, as done by inserts.
+    pub fn new<I, M>(root: &mut C, methods: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = M>,
+        M: Method<C, H>,
+    {
+        Self::new_with_format(root, methods, Format::default())
+    }
+
+    /// Creates a witness of the nodes visited by each of the search methods,
+    /// hashing in the given format
+    pub fn new_with_format<I, M>(
+        root: &mut C,
+        methods: I,
+        format: Format,
+    ) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = M>,
+        M: Method<C, H>,
+    {
+        // Make sure we compute and cache the hashes in the tree
+        root.root_hash_with(format)?;
+
+        let mut paths = vec![];
+        for mut method in methods {
+            let mut path = vec![];
+            let mut node = root.clone();
+            loop {
+                let ofs = match method.select(&node, 0) {
+                    SearchResult::Leaf(ofs) | SearchResult::Path(ofs) => ofs,
+                    SearchResult::None => node.children().len(),
+                };
+                path.push(ofs);
+                let child = match node.children().get(ofs) {
+                    Some(handle) => match handle.inner()? {
+                        HandleRef::Node(child) => (*child).clone(),
+                        _ => break,
+                    },
+                    None => break,
+                };
+                node = child;
             }
+            paths.push(path);
+        }
+
+        let nodes = collect(root, &paths, format)?;
+        Ok(Witness {
+            paths,
+            nodes,
+            format,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Replays an operation on the partial structure of the witness,
+    /// returning its result if the structure goes from the `before` to the
+    /// `after` root digest, or None otherwise.
+    ///
+    /// Operations touching nodes outside of the witness fail with a
+    /// `PrunedError`.
+    pub fn verify<F, R>(
+        &self,
+        before: &H::Digest,
+        after: &H::Digest,
+        op: F,
+    ) -> Option<R>
+    where
+        F: FnOnce(&mut C) -> io::Result<R>,
+    {
+        let mut partial = self.rebuild()?;
+        if partial.root_hash_with(self.format).ok()? != *before {
+            return None;
+        }
+        let result = op(&mut partial).ok()?;
+        if partial.root_hash_with(self.format).ok()? == *after {
+            Some(result)
         } else {
             None
         }
     }
+
+    // Rebuilds the partial structure from the nodes of the witness
+    fn rebuild(&self) -> Option<C> {
+        let mut nodes = self.nodes.iter();
+
+        // The nodes on the way down, with the offset they were reached by.
+        // Each node is placed in its parent when the walk leaves it.
+        let mut stack = vec![(0, nodes.next()?.clone())];
+        for step in walk(&self.paths) {
+            match step {
+                Step::Down(ofs) => stack.push((ofs, nodes.next()?.clone())),
+                Step::Up => {
+                    let (ofs, node) = stack.pop()?;
+                    let (_, parent) = stack.last_mut()?;
+                    let handle = parent.children_mut().get_mut(ofs)?;
+                    if handle.handle_type() != HandleType::Node {
+                        return None;
+                    }
+                    *handle = Handle::new_node(node);
+                }
+                Step::Holds(_) => (),
+            }
+        }
+        if nodes.next().is_some() {
+            return None;
+        }
+        stack.pop().map(|(_, root)| root)
+    }
+}
+
+impl<C, H> Content<H> for Witness<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        check_format(sink, self.format)?;
+        write_paths_and_nodes(&self.paths, &mut self.nodes, sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let (paths, nodes) =
+            read_paths_and_nodes(source, "Invalid Witness encoding")?;
+        Ok(Witness {
+            paths,
+            nodes,
+            format: source.format(),
+            _marker: PhantomData,
+        })
+    }
 }
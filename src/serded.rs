@@ -0,0 +1,840 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use bytehash::ByteHash;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor,
+};
+use serde::ser::{self, Serialize};
+
+use crate::content::Content;
+use crate::float::Float;
+use crate::format::EncodingVersion;
+use crate::sink::Sink;
+use crate::source::Source;
+
+/// Wrapper implementing `Content` for any type implementing the serde
+/// `Serialize` and `Deserialize` traits.
+///
+/// The serde data model is encoded in a compact, canonical binary format.
+/// Integers are fixed width big endian, sequences, strings and maps are
+/// prefixed by their length, and map entries are sorted by their encoded
+/// keys, so that equal maps always hash the same regardless of iteration
+/// order. Restoring rejects any encoding that does not serialize back to
+/// the exact same bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Serded<T>(pub T);
+
+impl<T, H> Content<H> for Serded<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        let bytes = to_bytes(&self.0, sink.format().version)?;
+        sink.write_all(&bytes)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut deserializer = Deserializer {
+            source,
+            read: vec![],
+            depth: 0,
+        };
+        let t = T::deserialize(&mut deserializer)?;
+        let version = deserializer.source.format().version;
+        if to_bytes(&t, version)? != deserializer.read {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Non-canonical serde encoding",
+            ));
+        }
+        Ok(Serded(t))
+    }
+}
+
+#[derive(Debug)]
+enum Error {
+    Io(io::Error),
+    Message(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Message(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Message(msg) => {
+                io::Error::new(io::ErrorKind::InvalidData, msg)
+            }
+        }
+    }
+}
+
+fn to_bytes<T: Serialize + ?Sized>(
+    t: &T,
+    version: EncodingVersion,
+) -> Result<Vec<u8>> {
+    let mut serializer = Serializer(vec![], version);
+    t.serialize(&mut serializer)?;
+    Ok(serializer.0)
+}
+
+// Serializes into a buffer, writing lengths in the given encoding version
+struct Serializer(Vec<u8>, EncodingVersion);
+
+// Writes the length of a sequence in front of its elements. The length is
+// usually known up front, otherwise the elements are serialized into a
+// separate buffer, and appended with their length at the end.
+struct Counted<'a> {
+    ser: &'a mut Serializer,
+    prefix: Prefix,
+    len: usize,
+}
+
+enum Prefix {
+    // The length has been written, and the elements must match it
+    Written(usize),
+    // Holds the output preceding the sequence until its length is known
+    Pending(Vec<u8>),
+}
+
+impl<'a> Counted<'a> {
+    fn new(ser: &'a mut Serializer, len: Option<usize>) -> Result<Self> {
+        let prefix = match len {
+            Some(len) => {
+                ser.1.write_len(&mut ser.0, len)?;
+                Prefix::Written(len)
+            }
+            None => Prefix::Pending(mem::replace(&mut ser.0, vec![])),
+        };
+        Ok(Counted {
+            ser,
+            prefix,
+            len: 0,
+        })
+    }
+
+    fn end(self) -> Result<()> {
+        match self.prefix {
+            Prefix::Written(len) if len == self.len => Ok(()),
+            Prefix::Written(_) => {
+                Err(Error::Message("Sequence length mismatch".into()))
+            }
+            Prefix::Pending(before) => {
+                let elements = mem::replace(&mut self.ser.0, before);
+                self.ser.1.write_len(&mut self.ser.0, self.len)?;
+                self.ser.0.extend(elements);
+                Ok(())
+            }
+        }
+    }
+}
+
+struct MapSerializer<'a> {
+    ser: &'a mut Serializer,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    key: Option<Vec<u8>>,
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Counted<'a>;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = MapSerializer<'a>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        Ok(self.0.write_u8(v as u8)?)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        Ok(self.0.write_i8(v)?)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        Ok(self.0.write_i16::<BigEndian>(v)?)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        Ok(self.0.write_i32::<BigEndian>(v)?)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        Ok(self.0.write_i64::<BigEndian>(v)?)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        Ok(self.0.write_i128::<BigEndian>(v)?)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        Ok(self.0.write_u8(v)?)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        Ok(self.0.write_u16::<BigEndian>(v)?)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        Ok(self.0.write_u32::<BigEndian>(v)?)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        Ok(self.0.write_u64::<BigEndian>(v)?)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        Ok(self.0.write_u128::<BigEndian>(v)?)
+    }
+
+    // All NaNs are written as the same canonical NaN, and negative zero as
+    // positive zero, like the `Content` implementation of floats
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        let v = v.canonical();
+        Ok(self.0.write_u32::<BigEndian>(v.to_bits())?)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        let v = v.canonical();
+        Ok(self.0.write_u64::<BigEndian>(v.to_bits())?)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.1.write_len(&mut self.0, v.len())?;
+        Ok(self.0.write_all(v)?)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.serialize_u8(0)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.serialize_u8(1)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+    ) -> Result<()> {
+        self.serialize_u32(index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.serialize_u32(index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Counted<'a>> {
+        Counted::new(self, len)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self> {
+        self.serialize_u32(index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<MapSerializer<'a>> {
+        Ok(MapSerializer {
+            ser: self,
+            entries: vec![],
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        index: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self> {
+        self.serialize_u32(index)?;
+        Ok(self)
+    }
+}
+
+impl<'a> ser::SerializeSeq for Counted<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<()> {
+        self.len += 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        Counted::end(self)
+    }
+}
+
+impl<'a> ser::SerializeTuple for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStruct for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeMap for MapSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(to_bytes(key, self.ser.1)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::Message("Map value without key".into()))?;
+        self.entries.push((key, to_bytes(value, self.ser.1)?));
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<()> {
+        self.entries.sort();
+        for pair in self.entries.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(Error::Message("Duplicate map key".into()));
+            }
+        }
+        self.ser.1.write_len(&mut self.ser.0, self.entries.len())?;
+        for (key, value) in self.entries {
+            self.ser.0.extend(key);
+            self.ser.0.extend(value);
+        }
+        Ok(())
+    }
+}
+
+// Deserializes from a source, keeping a copy of all bytes read to check
+// that the encoding is canonical
+struct Deserializer<'s, 'a, H: ByteHash> {
+    source: &'s mut Source<'a, H>,
+    read: Vec<u8>,
+    depth: usize,
+}
+
+impl<'s, 'a, H: ByteHash> Read for Deserializer<'s, 'a, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.source.read(buf)?;
+        self.read.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<'s, 'a, H: ByteHash> Deserializer<'s, 'a, H> {
+    fn read_len(&mut self) -> Result<usize> {
+        let len = self.source.format().version.read_len(self)?;
+        Ok(self.source.check_len(len)?)
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_len()?;
+        let mut bytes = vec![];
+        self.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(bytes)
+    }
+
+    fn nested<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        if self.depth >= self.source.limits().max_depth {
+            return Err(de::Error::custom(
+                "Decoding limit exceeded: nesting depth",
+            ));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+struct Access<'d, 's, 'a, H: ByteHash> {
+    de: &'d mut Deserializer<'s, 'a, H>,
+    remaining: usize,
+}
+
+impl<'de, 'd, 's, 'a, H: ByteHash> de::SeqAccess<'de>
+    for Access<'d, 's, 'a, H>
+{
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de, 'd, 's, 'a, H: ByteHash> de::MapAccess<'de>
+    for Access<'d, 's, 'a, H>
+{
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de, 'd, 's, 'a, H: ByteHash> de::EnumAccess<'de>
+    for &'d mut Deserializer<'s, 'a, H>
+{
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self)> {
+        let index = self.read_u32::<BigEndian>()?;
+        let value = seed
+            .deserialize(IntoDeserializer::<Error>::into_deserializer(index))?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'd, 's, 'a, H: ByteHash> de::VariantAccess<'de>
+    for &'d mut Deserializer<'s, 'a, H>
+{
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Access {
+            de: self,
+            remaining: len,
+        })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.tuple_variant(fields.len(), visitor)
+    }
+}
+
+impl<'de, 'd, 's, 'a, H: ByteHash> de::Deserializer<'de>
+    for &'d mut Deserializer<'s, 'a, H>
+{
+    type Error = Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value> {
+        Err(de::Error::custom("Serded format is not self-describing"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(de::Error::custom("Invalid bool encoding")),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(self.read_i8()?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(self.read_i16::<BigEndian>()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(self.read_i32::<BigEndian>()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(self.read_i64::<BigEndian>()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i128(self.read_i128::<BigEndian>()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(self.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(self.read_u16::<BigEndian>()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.read_u32::<BigEndian>()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.read_u64::<BigEndian>()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u128(self.read_u128::<BigEndian>()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_bits(self.read_u32::<BigEndian>()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(f64::from_bits(self.read_u64::<BigEndian>()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let code = self.read_u32::<BigEndian>()?;
+        match std::char::from_u32(code) {
+            Some(c) => visitor.visit_char(c),
+            None => Err(de::Error::custom("Invalid char encoding")),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value> {
+        match String::from_utf8(self.read_bytes()?) {
+            Ok(string) => visitor.visit_string(string),
+            Err(_) => Err(de::Error::custom("Invalid utf8 encoding")),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.read_bytes()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.read_u8()? {
+            0 => visitor.visit_none(),
+            1 => self.nested(|de| visitor.visit_some(de)),
+            _ => Err(de::Error::custom("Invalid Option encoding")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.nested(|de| visitor.visit_newtype_struct(de))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_len()?;
+        self.nested(|de| visitor.visit_seq(Access { de, remaining: len }))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.nested(|de| visitor.visit_seq(Access { de, remaining: len }))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_len()?;
+        self.nested(|de| visitor.visit_map(Access { de, remaining: len }))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.nested(|de| visitor.visit_enum(de))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+}
@@ -4,56 +4,168 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::fs::File;
 use std::hash::Hasher;
-use std::io;
+use std::io::{self, Seek, SeekFrom, Write};
 
 use bytehash::{ByteHash, State};
 
+use crate::format::Format;
 use crate::store::Store;
 
+// Nodes larger than this are spooled to a temporary file while written
+const SPOOL_MEMORY: usize = 1024 * 1024;
+
+/// The bytes of a node being written, before its hash is known.
+///
+/// Kept in memory, unless the node grows large, in which case the bytes are
+/// spilled to a temporary file. Without the `filesystem` feature there are no
+/// temporary files, and nodes are always kept in memory.
+#[derive(Default)]
+pub(crate) struct Spool {
+    len: u64,
+    memory: Vec<u8>,
+    file: Option<File>,
+}
+
+/// The bytes of a finished `Spool`
+pub(crate) enum Spooled {
+    Memory(Vec<u8>),
+    File(File, u64),
+}
+
+impl Spool {
+    pub(crate) fn finish(self) -> io::Result<Spooled> {
+        match self.file {
+            None => Ok(Spooled::Memory(self.memory)),
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Spooled::File(file, self.len))
+            }
+        }
+    }
+
+    #[cfg(feature = "filesystem")]
+    fn spill(&mut self) -> io::Result<()> {
+        let mut file = tempfile::tempfile()?;
+        file.write_all(&self.memory)?;
+        self.memory = vec![];
+        self.file = Some(file);
+        Ok(())
+    }
+
+    // Nothing to spill to, the node stays in memory
+    #[cfg(not(feature = "filesystem"))]
+    fn spill(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > SPOOL_MEMORY {
+            self.spill()?;
+        }
+        let n = match self.file {
+            Some(ref mut file) => file.write(buf)?,
+            None => self.memory.write(buf)?,
+        };
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The context a node is hashed in.
+///
+/// In stores with `Format::domain_separation` set, the domain is appended to
+/// the bytes of every node when hashing, so that a leaf value can never hash
+/// to the same digest as a tree node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashDomain {
+    /// Values without child handles
+    Leaf = 0,
+    /// Tree nodes, containing child handles
+    Node = 1,
+}
+
+fn finalize<H: ByteHash>(
+    mut state: H::State,
+    domain: HashDomain,
+    format: Format,
+) -> H::Digest {
+    if format.domain_separation {
+        state.write(&[domain as u8]);
+    }
+    state.fin()
+}
+
 /// A sink for bytes, used in implementing `Content`
-pub enum Sink<'a, H: ByteHash> {
-    /// Sink is only hashing
-    DryRun(H::State),
-    /// Sink is writing to storage and hashing
-    Writing(Vec<u8>, &'a Store<H>),
-    /// Sink is writing to storage with cached hash
-    WritingCached(Vec<u8>, H::Digest, &'a Store<H>),
+pub struct Sink<'a, H: ByteHash>(SinkInner<'a, H>);
+
+enum SinkInner<'a, H: ByteHash> {
+    // Only hashing, in the given format
+    DryRun(H::State, HashDomain, Format),
+    // Writing to storage and hashing
+    Writing(H::State, HashDomain, Spool, &'a Store<H>),
+    // Writing to storage with cached hash
+    WritingCached(Spool, H::Digest, &'a Store<H>),
 }
 
 impl<'a, H: ByteHash> Sink<'a, H> {
-    pub(crate) fn new(store: &'a Store<H>) -> Self {
-        Sink::Writing(vec![], store)
+    pub(crate) fn new(store: &'a Store<H>, domain: HashDomain) -> Self {
+        Sink(SinkInner::Writing(
+            H::state(),
+            domain,
+            Spool::default(),
+            store,
+        ))
     }
 
-    pub(crate) fn new_dry() -> Self {
-        Sink::DryRun(H::state())
+    pub(crate) fn new_dry(format: Format, domain: HashDomain) -> Self {
+        Sink(SinkInner::DryRun(H::state(), domain, format))
     }
 
     pub(crate) fn new_cached(hash: H::Digest, store: &'a Store<H>) -> Self {
-        Sink::WritingCached(vec![], hash, store)
+        Sink(SinkInner::WritingCached(Spool::default(), hash, store))
     }
 
     pub(crate) fn store(&self) -> Option<&Store<H>> {
-        match self {
-            Sink::Writing(_, ref store)
-            | Sink::WritingCached(_, _, ref store) => Some(store),
-            Sink::DryRun(_) => None,
+        match self.0 {
+            SinkInner::Writing(_, _, _, ref store)
+            | SinkInner::WritingCached(_, _, ref store) => Some(store),
+            SinkInner::DryRun(..) => None,
+        }
+    }
+
+    /// Returns the format the sink is writing in
+    pub fn format(&self) -> Format {
+        match self.0 {
+            SinkInner::DryRun(_, _, format) => format,
+            SinkInner::Writing(_, _, _, store)
+            | SinkInner::WritingCached(_, _, store) => store.format(),
         }
     }
 
     pub(crate) fn fin(self) -> io::Result<H::Digest> {
-        match self {
-            Sink::DryRun(state) => Ok(state.fin()),
-            Sink::Writing(bytes, store) => {
-                let mut hasher = H::state();
-                hasher.write(&bytes);
-                let hash = hasher.fin();
-                store.put(hash, bytes)?;
+        match self.0 {
+            SinkInner::DryRun(state, domain, format) => {
+                Ok(finalize::<H>(state, domain, format))
+            }
+            SinkInner::Writing(state, domain, spool, store) => {
+                // The bytes are hashed as they are written
+                let hash = finalize::<H>(state, domain, store.format());
+                store.put_spool(hash, spool)?;
                 Ok(hash)
             }
-            Sink::WritingCached(bytes, hash, store) => {
-                store.put(hash, bytes)?;
+            SinkInner::WritingCached(spool, hash, store) => {
+                store.put_spool(hash, spool)?;
                 Ok(hash)
             }
         }
@@ -62,15 +174,18 @@ impl<'a, H: ByteHash> Sink<'a, H> {
 
 impl<'a, H: ByteHash> io::Write for Sink<'a, H> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self {
-            Sink::DryRun(state) => {
+        match self.0 {
+            SinkInner::DryRun(ref mut state, ..) => {
                 // Note, this write is from Hasher, not from io;
                 state.write(buf);
                 Ok(buf.len())
             }
-            Sink::Writing(bytes, ..) | Sink::WritingCached(bytes, ..) => {
-                bytes.write(buf)
+            SinkInner::Writing(ref mut state, _, ref mut spool, _) => {
+                spool.write_all(buf)?;
+                state.write(buf);
+                Ok(buf.len())
             }
+            SinkInner::WritingCached(ref mut spool, ..) => spool.write(buf),
         }
     }
 
@@ -8,26 +8,147 @@ use std::io::{self, Read};
 
 use bytehash::ByteHash;
 
+use crate::format::Format;
 use crate::store::Store;
 
+/// Limits enforced when decoding data from a `Source`.
+///
+/// Stores enforce `Limits::untrusted()` by default, stores of trusted data
+/// may opt out with `Store::set_limits` and `Limits::unbounded()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of elements in a decoded collection
+    pub max_len: u64,
+    /// Maximum number of bytes read when decoding a single node
+    pub max_bytes: u64,
+    /// Maximum nesting depth of decoded values
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits::untrusted()
+    }
+}
+
+impl Limits {
+    /// No limits at all, for trusted data only
+    pub fn unbounded() -> Self {
+        Limits {
+            max_len: u64::max_value(),
+            max_bytes: u64::max_value(),
+            max_depth: usize::max_value(),
+        }
+    }
+
+    /// Conservative limits for decoding untrusted data
+    pub fn untrusted() -> Self {
+        Limits {
+            max_len: 1 << 24,
+            max_bytes: 1 << 26,
+            max_depth: 128,
+        }
+    }
+}
+
+fn exceeded(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Decoding limit exceeded: {}", what),
+    )
+}
+
 /// A source of bytes, used in implementing `Content`
 pub struct Source<'a, H: ByteHash> {
     read: Box<dyn Read + 'a>,
     store: &'a Store<H>,
+    digest: H::Digest,
+    limits: Limits,
+    bytes_read: u64,
+    depth: usize,
 }
 
 impl<'a, H: ByteHash> Source<'a, H> {
-    pub(crate) fn new(read: Box<dyn Read + 'a>, store: &'a Store<H>) -> Self {
-        Source { read, store }
+    pub(crate) fn new(
+        read: Box<dyn Read + 'a>,
+        store: &'a Store<H>,
+        digest: H::Digest,
+    ) -> Self {
+        Source {
+            read,
+            limits: store.limits(),
+            store,
+            digest,
+            bytes_read: 0,
+            depth: 0,
+        }
     }
 
     pub(crate) fn store(&self) -> &Store<H> {
         &self.store
     }
+
+    /// Returns the format of the store the source is reading from
+    pub fn format(&self) -> Format {
+        self.store.format()
+    }
+
+    /// Returns the digest of the node being read
+    pub fn digest(&self) -> &H::Digest {
+        &self.digest
+    }
+
+    /// Checks that the whole node has been read, rejecting trailing bytes
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut byte = [0u8];
+        match self.read(&mut byte)? {
+            0 => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Trailing bytes in encoding",
+            )),
+        }
+    }
+
+    /// Returns the limits enforced by this source
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Checks a decoded collection length against the limits, returning it
+    /// as a `usize`
+    pub fn check_len(&self, len: u64) -> io::Result<usize> {
+        if len > self.limits.max_len {
+            return Err(exceeded("collection length"));
+        }
+        if len > usize::max_value() as u64 {
+            return Err(exceeded("collection length"));
+        }
+        Ok(len as usize)
+    }
+
+    /// Decodes a nested value using `f`, enforcing the maximum depth
+    pub fn nested<R, F>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut Self) -> io::Result<R>,
+    {
+        if self.depth >= self.limits.max_depth {
+            return Err(exceeded("nesting depth"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
 }
 
 impl<'a, H: ByteHash> Read for Source<'a, H> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.read.read(buf)
+        let n = self.read.read(buf)?;
+        self.bytes_read += n as u64;
+        if self.bytes_read > self.limits.max_bytes {
+            return Err(exceeded("node size"));
+        }
+        Ok(n)
     }
 }
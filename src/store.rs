@@ -7,18 +7,22 @@
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{fmt, io};
 
 use arrayvec::ArrayVec;
-use bytehash::ByteHash;
+use base64::{decode_config, encode_config, URL_SAFE_NO_PAD};
+use bytehash::{Blake2b, ByteHash};
 use cache::Cache;
 use parking_lot::RwLock;
 
 use crate::backend::{Backend, Ephemeral, Persistant, PutResult};
 use crate::content::Content;
-use crate::sink::Sink;
-use crate::source::Source;
+use crate::format::Format;
+use crate::sink::{Sink, Spool, Spooled};
+use crate::source::{Limits, Source};
 
 /// The main store type, wrapping backend and cache functionality
 #[derive(Clone)]
@@ -33,6 +37,9 @@ pub struct StoreInner<H: ByteHash> {
     generations: ArrayVec<[RwLock<Box<dyn Backend<H>>>; GENERATIONS]>,
     #[allow(unused)]
     cache: Cache<H::Digest>,
+    limits: RwLock<Limits>,
+    canonical: AtomicBool,
+    format: Format,
 }
 
 impl<H: ByteHash> fmt::Debug for Store<H> {
@@ -75,6 +82,29 @@ impl<T: Content<H>, H: ByteHash> Snapshot<T, H> {
     pub(crate) fn as_bytes(&self) -> &[u8] {
         self.hash.as_ref()
     }
+
+    pub(crate) fn format(&self) -> Format {
+        self.store.format()
+    }
+
+    // The hash is in the format of the store, and cannot be had in another
+    pub(crate) fn hash_with(&self, format: Format) -> io::Result<H::Digest> {
+        if self.format() == format {
+            Ok(self.hash)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Format mismatch with the store of a persisted node",
+            ))
+        }
+    }
+}
+
+impl<T, H: ByteHash> Snapshot<T, H> {
+    /// Returns a store-independent reference to the snapshot
+    pub fn id(&self) -> SnapshotId<H> {
+        SnapshotId(self.hash)
+    }
 }
 
 impl<T, H: ByteHash> Deref for Snapshot<T, H> {
@@ -84,37 +114,219 @@ impl<T, H: ByteHash> Deref for Snapshot<T, H> {
     }
 }
 
+impl<T, H: HashName> fmt::Display for Snapshot<T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.id(), f)
+    }
+}
+
+/// Names a hash algorithm in textual snapshot encodings
+pub trait HashName: ByteHash {
+    /// Stable, lowercase name of the hash algorithm
+    const NAME: &'static str;
+}
+
+impl HashName for Blake2b {
+    const NAME: &'static str = "blake2b";
+}
+
+/// A store-independent reference to a snapshot, for use in logs, RPC and
+/// configuration files.
+///
+/// Encoded as text in the form `<algorithm>:<digest>`, with the digest in
+/// lowercase hex, or in url-safe base64 using the alternate flag (`{:#}`).
+/// Parsing accepts both forms.
+pub struct SnapshotId<H: ByteHash>(H::Digest);
+
+impl<H: ByteHash> SnapshotId<H> {
+    /// Creates a snapshot reference from a digest
+    pub fn new(digest: H::Digest) -> Self {
+        SnapshotId(digest)
+    }
+
+    /// Returns a reference to the referenced digest
+    pub fn digest(&self) -> &H::Digest {
+        &self.0
+    }
+}
+
+impl<H: HashName> SnapshotId<H> {
+    /// Returns the base64 text encoding of the snapshot reference
+    pub fn to_base64(&self) -> String {
+        format!("{:#}", self)
+    }
+}
+
+impl<H: ByteHash> Clone for SnapshotId<H> {
+    fn clone(&self) -> Self {
+        SnapshotId(self.0)
+    }
+}
+
+impl<H: ByteHash> PartialEq for SnapshotId<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<H: ByteHash> Eq for SnapshotId<H> {}
+
+impl<H: HashName> fmt::Debug for SnapshotId<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SnapshotId({})", self)
+    }
+}
+
+impl<H: HashName> fmt::Display for SnapshotId<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", H::NAME)?;
+        if f.alternate() {
+            write!(f, "{}", encode_config(self.0.as_ref(), URL_SAFE_NO_PAD))
+        } else {
+            for byte in self.0.as_ref() {
+                write!(f, "{:02x}", byte)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Only lowercase hex digits are accepted, as written by `Display`
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+impl<H: HashName> FromStr for SnapshotId<H> {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut split = s.splitn(2, ':');
+        let encoded = match (split.next(), split.next()) {
+            (Some(name), Some(encoded)) if name == H::NAME => encoded,
+            (Some(_), Some(_)) => {
+                return Err(invalid("Hash algorithm mismatch"))
+            }
+            _ => return Err(invalid("Missing hash algorithm")),
+        };
+
+        let mut digest = H::Digest::default();
+        let len = digest.as_ref().len();
+
+        if encoded.len() == len * 2 {
+            let encoded = encoded.as_bytes();
+            for (i, byte) in digest.as_mut().iter_mut().enumerate() {
+                let hi = hex_digit(encoded[i * 2]);
+                let lo = hex_digit(encoded[i * 2 + 1]);
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => *byte = hi << 4 | lo,
+                    _ => return Err(invalid("Invalid hex digest")),
+                }
+            }
+        } else {
+            let bytes = decode_config(encoded, URL_SAFE_NO_PAD)
+                .map_err(|_| invalid("Invalid base64 digest"))?;
+            if bytes.len() != len {
+                return Err(invalid("Invalid digest length"));
+            }
+            digest.as_mut().copy_from_slice(&bytes);
+        }
+        Ok(SnapshotId(digest))
+    }
+}
+
 impl<H: ByteHash> Store<H> {
-    /// Creates a new Store at `path`
+    /// Creates a new Store at `path`, in the format recorded with it, or in
+    /// the default format if the store is new
     pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
-        let pers = Persistant::new(path)?;
-        let mut generations = ArrayVec::new();
-        generations.push(RwLock::new(Box::new(pers) as Box<dyn Backend<H>>));
+        let mut pers = Persistant::new(path)?;
+        let format = match Backend::<H>::format(&pers)? {
+            Some(format) => format,
+            None => {
+                let format = Format::default();
+                Backend::<H>::set_format(&mut pers, format)?;
+                format
+            }
+        };
+        Ok(Self::with_backend(Box::new(pers), format))
+    }
 
-        Ok(Store(Arc::new(StoreInner {
-            generations,
-            cache: Cache::new(32, 4096),
-        })))
+    /// Creates a new Store at `path` in the given format, failing if the
+    /// store was created in another format
+    pub fn new_with_format<P: Into<PathBuf>>(
+        path: P,
+        format: Format,
+    ) -> io::Result<Self> {
+        let mut pers = Persistant::new(path)?;
+        match Backend::<H>::format(&pers)? {
+            Some(recorded) if recorded != format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Store format mismatch",
+                ))
+            }
+            Some(_) => (),
+            None => Backend::<H>::set_format(&mut pers, format)?,
+        }
+        Ok(Self::with_backend(Box::new(pers), format))
     }
 
     /// Creates a new ephemeral (in-memory only) Store
     pub fn ephemeral() -> Self {
-        let pers = Ephemeral::new();
+        Self::ephemeral_with_format(Format::default())
+    }
+
+    /// Creates a new ephemeral (in-memory only) Store in the given format
+    pub fn ephemeral_with_format(format: Format) -> Self {
+        Self::with_backend(Box::new(Ephemeral::new()), format)
+    }
+
+    fn with_backend(backend: Box<dyn Backend<H>>, format: Format) -> Self {
         let mut generations = ArrayVec::new();
-        generations.push(RwLock::new(Box::new(pers) as Box<dyn Backend<H>>));
+        generations.push(RwLock::new(backend));
 
         Store(Arc::new(StoreInner {
             generations,
             cache: Cache::new(32, 4096),
+            limits: RwLock::new(Limits::default()),
+            canonical: AtomicBool::new(false),
+            format,
         }))
     }
 
+    /// Returns the format of the data in the store
+    pub fn format(&self) -> Format {
+        self.0.format
+    }
+
+    /// Returns the limits enforced when restoring content from the store
+    pub fn limits(&self) -> Limits {
+        *self.0.limits.read()
+    }
+
+    /// Sets the limits enforced when restoring content from the store
+    pub fn set_limits(&self, limits: Limits) {
+        *self.0.limits.write() = limits
+    }
+
+    /// Requires every restored node to be in canonical encoding, and to
+    /// match the digest it was loaded by. For stores backed by untrusted
+    /// data.
+    pub fn set_canonical(&self, canonical: bool) {
+        self.0.canonical.store(canonical, Ordering::Relaxed)
+    }
+
     /// Persists Content to the store, returning a Snapshot
     pub fn persist<T: Content<H>>(
         &self,
         content: &mut T,
     ) -> io::Result<Snapshot<T, H>> {
-        let mut sink = Sink::new(self);
+        let mut sink = Sink::new(self, T::hash_domain());
         content.persist(&mut sink)?;
         Ok(Snapshot {
             hash: sink.fin()?,
@@ -132,12 +344,29 @@ impl<H: ByteHash> Store<H> {
         Ok(())
     }
 
-    pub(crate) fn put(
+    pub(crate) fn put_spool(
         &self,
         hash: H::Digest,
-        bytes: Vec<u8>,
+        spool: Spool,
     ) -> io::Result<PutResult> {
-        self.0.generations[0].write().put(hash, bytes)
+        match spool.finish()? {
+            Spooled::Memory(bytes) => {
+                self.0.generations[0].write().put(hash, bytes)
+            }
+            Spooled::File(mut file, len) => self.0.generations[0]
+                .write()
+                .put_stream(hash, len, &mut file),
+        }
+    }
+
+    /// Constructs a snapshot from a digest obtained elsewhere, checking that
+    /// the digest is present in the store and decodes as `T`
+    pub fn snapshot_from_digest<T: Content<H>>(
+        &self,
+        digest: &H::Digest,
+    ) -> io::Result<Snapshot<T, H>> {
+        self.get_hash::<T>(digest)?;
+        Ok(Snapshot::new(*digest, self))
     }
 
     /// Restores a snapshot from Backend
@@ -154,8 +383,13 @@ impl<H: ByteHash> Store<H> {
     ) -> io::Result<T> {
         for gen in self.0.generations.as_ref() {
             if let Ok(read) = gen.read().get(hash) {
-                let mut source = Source::new(read, self);
-                return T::restore(&mut source);
+                let mut source = Source::new(read, self, *hash);
+                if self.0.canonical.load(Ordering::Relaxed) {
+                    return T::restore_canonical(&mut source);
+                }
+                let restored = T::restore(&mut source)?;
+                source.finish()?;
+                return Ok(restored);
             }
         }
         Err(io::Error::new(io::ErrorKind::NotFound, "Data not found"))
@@ -0,0 +1,192 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use bytehash::ByteHash;
+
+use crate::content::Content;
+use crate::sink::Sink;
+use crate::source::Source;
+
+/// Wrapper encoding an integer in LEB128 variable length form, using as few
+/// bytes as possible. Signed integers are zigzag encoded, so that numbers
+/// of small magnitude stay small.
+///
+/// Only the minimal encoding of each value is accepted on restore.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Varint<T>(pub T);
+
+impl<T> From<T> for Varint<T> {
+    fn from(t: T) -> Self {
+        Varint(t)
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn write_varint<W: Write>(w: &mut W, mut n: u128) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+// Reads a varint of at most `bits` bits, rejecting non-minimal encodings
+pub(crate) fn read_varint<R: Read>(r: &mut R, bits: u32) -> io::Result<u128> {
+    let mut n = 0u128;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        r.read_exact(&mut byte)?;
+        let value = (byte[0] & 0x7f) as u128;
+
+        if shift >= bits || (bits - shift < 7 && value >> (bits - shift) != 0) {
+            return Err(invalid("Varint out of range"));
+        }
+        n |= value << shift;
+
+        if byte[0] & 0x80 == 0 {
+            if byte[0] == 0 && shift > 0 {
+                return Err(invalid("Non-minimal varint encoding"));
+            }
+            return Ok(n);
+        }
+        shift += 7;
+    }
+}
+
+macro_rules! unsigned {
+    ($t:ty : $bits:expr) => {
+        impl<H: ByteHash> Content<H> for Varint<$t> {
+            fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+                write_varint(sink, self.0 as u128)
+            }
+
+            fn restore(source: &mut Source<H>) -> io::Result<Self> {
+                let n = read_varint(source, $bits)?;
+                <$t>::try_from(n)
+                    .map(Varint)
+                    .map_err(|_| invalid("Varint out of range"))
+            }
+        }
+    };
+}
+
+macro_rules! signed {
+    ($t:ty : $bits:expr) => {
+        impl<H: ByteHash> Content<H> for Varint<$t> {
+            fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+                let n = self.0 as i128;
+                let zigzag = ((n << 1) ^ (n >> 127)) as u128;
+                write_varint(sink, zigzag)
+            }
+
+            fn restore(source: &mut Source<H>) -> io::Result<Self> {
+                let zigzag = read_varint(source, $bits)?;
+                let n = (zigzag >> 1) as i128 ^ -((zigzag & 1) as i128);
+                <$t>::try_from(n)
+                    .map(Varint)
+                    .map_err(|_| invalid("Varint out of range"))
+            }
+        }
+    };
+}
+
+unsigned!(u8: 8);
+unsigned!(u16: 16);
+unsigned!(u32: 32);
+unsigned!(u64: 64);
+unsigned!(u128: 128);
+// Platform dependent sizes are restricted to 64 bit
+unsigned!(usize: 64);
+
+signed!(i8: 8);
+signed!(i16: 16);
+signed!(i32: 32);
+signed!(i64: 64);
+signed!(i128: 128);
+signed!(isize: 64);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fmt::Debug;
+
+    use crate::Store;
+    use bytehash::Blake2b;
+
+    fn roundtrip<T>(t: T)
+    where
+        T: Copy,
+        Varint<T>: Content<Blake2b> + PartialEq + Debug,
+    {
+        let store = Store::<Blake2b>::ephemeral();
+        let snapshot = store.persist(&mut Varint(t)).unwrap();
+        assert_eq!(store.restore(&snapshot).unwrap(), Varint(t));
+    }
+
+    // Restores `T` from a raw encoding
+    fn decode<T: Content<Blake2b>, B: Content<Blake2b>>(
+        mut bytes: B,
+    ) -> io::Result<T> {
+        let store = Store::<Blake2b>::ephemeral();
+        let snapshot = store.persist(&mut bytes).unwrap();
+        store.restore(&store.snapshot_from_digest::<T>(&snapshot)?)
+    }
+
+    #[test]
+    fn roundtrips() {
+        for i in 0..1024 {
+            roundtrip(i as u16);
+            roundtrip(i as i16 - 512);
+        }
+        roundtrip(u8::max_value());
+        roundtrip(u64::max_value());
+        roundtrip(u128::max_value());
+        roundtrip(usize::max_value());
+        roundtrip(i8::min_value());
+        roundtrip(i64::min_value());
+        roundtrip(i128::min_value());
+        roundtrip(i128::max_value());
+        roundtrip(isize::min_value());
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(decode::<Varint<u64>, _>(0u8).unwrap(), Varint(0));
+        assert_eq!(
+            decode::<Varint<u64>, _>([0xacu8, 0x02]).unwrap(),
+            Varint(300)
+        );
+        assert_eq!(decode::<Varint<i32>, _>(3u8).unwrap(), Varint(-2));
+        assert_eq!(
+            decode::<[u8; 10], _>(Varint(u64::max_value())).unwrap(),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn reject_invalid() {
+        // non-minimal
+        assert!(decode::<Varint<u64>, _>([0x80u8, 0x00]).is_err());
+        assert!(decode::<Varint<u64>, _>([0x81u8, 0x80, 0x00]).is_err());
+        // out of range
+        assert!(decode::<Varint<u8>, _>([0x80u8, 0x02]).is_err());
+        assert!(decode::<Varint<u64>, _>([0xffu8; 11]).is_err());
+        assert!(decode::<Varint<i8>, _>([0x80u8, 0x02]).is_err());
+        // truncated
+        assert!(decode::<Varint<u64>, _>(0x80u8).is_err());
+    }
+}
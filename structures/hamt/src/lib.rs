@@ -16,8 +16,9 @@ use std::mem;
 
 use kelvin::{
     annotations::{Annotation, Cardinality, Void},
-    ByteHash, Compound, Content, Handle, HandleMut, HandleRef, HandleType,
-    Method, SearchResult, Sink, Source, ValPath, ValPathMut, KV,
+    ByteHash, Compound, Content, Format, Handle, HandleMut, HandleRef,
+    HandleType, HashDomain, Method, Proof, SearchResult, Sink, Source, ValPath,
+    ValPathMut, Witness, KV,
 };
 
 /// Default HAMT-map without annotations
@@ -81,6 +82,9 @@ where
     K: Eq + Hash,
     Self::Leaf: Borrow<KV<K, V>> + From<KV<K, V>> + Into<KV<K, V>>,
 {
+    // Number of levels addressed by each byte of the hash
+    const DEPTH_PER_BYTE: usize;
+
     fn sub_insert(
         &mut self,
         depth: usize,
@@ -195,6 +199,78 @@ where
         }
     }
 
+    // The path of nodes down to the slot of the key, ending in an empty slot
+    // or a leaf with a different key, or None if the key is present
+    fn absence_path<O>(&self, k: &O) -> io::Result<Option<Vec<(Self, usize)>>>
+    where
+        O: ?Sized + Hash + Eq,
+        K: Borrow<O>,
+    {
+        let h = H::hash(k);
+        let mut path = vec![];
+        let mut node = self.clone();
+        let mut depth = 0;
+        loop {
+            let s = Self::select_slot(h.as_ref(), depth);
+            let next = match node.children()[s].inner()? {
+                HandleRef::None => None,
+                HandleRef::Leaf(leaf) => {
+                    let KV { key, val: _ } = leaf.borrow();
+                    if key.borrow() == k {
+                        return Ok(None);
+                    }
+                    None
+                }
+                HandleRef::Node(child) => Some((*child).clone()),
+            };
+            path.push((node, s));
+            match next {
+                Some(child) => node = child,
+                None => return Ok(Some(path)),
+            }
+            depth += 1;
+        }
+    }
+
+    fn verify_absence<O>(
+        proof: &Proof<Self, H>,
+        k: &O,
+        root: &H::Digest,
+    ) -> bool
+    where
+        O: ?Sized + Hash + Eq,
+        K: Borrow<O>,
+    {
+        let h = H::hash(k);
+        let levels: Vec<_> = proof.levels().collect();
+        if levels.is_empty()
+            || levels.len() > h.as_ref().len() * Self::DEPTH_PER_BYTE
+            || !proof.verify_path(root)
+        {
+            return false;
+        }
+        for (depth, (node, ofs)) in levels.iter().enumerate() {
+            // the path must follow the hash of the key
+            if *ofs != Self::select_slot(h.as_ref(), depth) {
+                return false;
+            }
+            let child = &node.children()[*ofs];
+            let last = depth + 1 == levels.len();
+            match (child.handle_type(), last) {
+                (HandleType::Node, false) | (HandleType::None, true) => (),
+                (HandleType::Leaf, true) => {
+                    let KV { key, val: _ } =
+                        child.leaf().expect("leaf").borrow();
+                    if key.borrow() == k {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
     fn remove_singleton(&mut self) -> io::Result<Option<KV<K, V>>> {
         let mut singleton = None;
 
@@ -309,6 +385,7 @@ where
     A: Annotation<KV<K, V>, H>,
     H: ByteHash,
 {
+    const DEPTH_PER_BYTE: usize = 2;
 }
 
 impl<K, V, A, H> HAMTTrait<K, V, H> for NarrowHAMT<K, V, A, H>
@@ -318,6 +395,7 @@ where
     A: Annotation<KV<K, V>, H>,
     H: ByteHash,
 {
+    const DEPTH_PER_BYTE: usize = 4;
 }
 
 impl<K, V, A, H> SlotSelect for HAMT<K, V, A, H>
@@ -461,6 +539,95 @@ where
     }
 }
 
+// Proofs and witnesses, shared by both widths of the HAMT
+macro_rules! proofs {
+    ($hamt:ident) => {
+        impl<K, V, A, H> $hamt<K, V, A, H>
+        where
+            K: Content<H> + Eq + Hash,
+            V: Content<H>,
+            A: Annotation<KV<K, V>, H>,
+            H: ByteHash,
+        {
+            /// Creates a witness of the nodes touched by inserting the key,
+            /// to be replayed with `Witness::verify`
+            pub fn insert_witness<O>(
+                &mut self,
+                k: &O,
+            ) -> io::Result<Witness<Self, H>>
+            where
+                O: ?Sized + Hash + Eq,
+                K: Borrow<O>,
+            {
+                Witness::new(self, Some(HAMTSearch::from(k)))
+            }
+
+            /// Creates a proof that the key is absent from the map, or
+            /// returns None if the key is present
+            pub fn prove_absent<O>(
+                &self,
+                k: &O,
+            ) -> io::Result<Option<Proof<Self, H>>>
+            where
+                O: ?Sized + Hash + Eq,
+                K: Borrow<O>,
+            {
+                self.prove_absent_with_format(k, Format::default())
+            }
+
+            /// Creates a proof that the key is absent from the map, hashing
+            /// in the given format
+            pub fn prove_absent_with_format<O>(
+                &self,
+                k: &O,
+                format: Format,
+            ) -> io::Result<Option<Proof<Self, H>>>
+            where
+                O: ?Sized + Hash + Eq,
+                K: Borrow<O>,
+            {
+                self.absence_path(k)?
+                    .map(|path| Proof::from_path_with_format(path, format))
+                    .transpose()
+            }
+
+            /// Verifies a proof that the key is absent from the map with the
+            /// given root digest
+            pub fn verify_absent<O>(
+                proof: &Proof<Self, H>,
+                k: &O,
+                root: &H::Digest,
+            ) -> bool
+            where
+                O: ?Sized + Hash + Eq,
+                K: Borrow<O>,
+            {
+                Self::verify_absence(proof, k, root)
+            }
+        }
+    };
+}
+
+proofs!(HAMT);
+proofs!(NarrowHAMT);
+
+fn invalid_encoding() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid HAMT encoding")
+}
+
+// Restores a handle marked as present in the mask, rejecting empty handles
+fn restore_present<C, H>(source: &mut Source<H>) -> io::Result<Handle<C, H>>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    let handle = Handle::restore(source)?;
+    if let HandleType::None = handle.handle_type() {
+        return Err(invalid_encoding());
+    }
+    Ok(handle)
+}
+
 impl<K, V, A, H> Content<H> for HAMT<K, V, A, H>
 where
     K: Content<H>,
@@ -493,11 +660,15 @@ where
         let mask = <u16 as Content<H>>::restore(source)?;
         for (i, handle) in bucket.iter_mut().enumerate() {
             if mask & (1 << i) != 0 {
-                *handle = Handle::restore(source)?
+                *handle = restore_present(source)?
             }
         }
         Ok(HAMT(bucket))
     }
+
+    fn hash_domain() -> HashDomain {
+        HashDomain::Node
+    }
 }
 
 impl<K, V, A, H> Content<H> for NarrowHAMT<K, V, A, H>
@@ -530,13 +701,20 @@ where
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
         let mut bucket: [Handle<Self, H>; 4] = Default::default();
         let mask = <u8 as Content<H>>::restore(source)?;
+        if mask >> 4 != 0 {
+            return Err(invalid_encoding());
+        }
         for (i, handle) in bucket.iter_mut().enumerate() {
             if mask & (1 << i) != 0 {
-                *handle = Handle::restore(source)?
+                *handle = restore_present(source)?
             }
         }
         Ok(NarrowHAMT(bucket))
     }
+
+    fn hash_domain() -> HashDomain {
+        HashDomain::Node
+    }
 }
 
 impl<K, V, A, H> Compound<H> for HAMT<K, V, A, H>
@@ -581,7 +759,7 @@ where
 mod test {
     use super::*;
 
-    use kelvin::{quickcheck_map, Blake2b, Erased, Store};
+    use kelvin::{quickcheck_map, Blake2b, EncodingVersion, Erased, Store};
 
     #[test]
     fn trivial_map() {
@@ -660,6 +838,86 @@ mod test {
         }
     }
 
+    #[test]
+    fn reject_non_canonical() {
+        let store = Store::<Blake2b>::ephemeral();
+
+        // mask marks the first slot as present, but the handle is empty
+        let snapshot = store.persist(&mut (1u16, 0u8)).unwrap();
+        assert!(store
+            .snapshot_from_digest::<DefaultHAMTMap<u32, u32, Blake2b>>(
+                snapshot.hash()
+            )
+            .is_err());
+
+        let snapshot = store.persist(&mut (0u16, 0u8)).unwrap();
+        assert!(store
+            .snapshot_from_digest::<DefaultHAMTMap<u32, u32, Blake2b>>(
+                snapshot.hash()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn exclusion_proofs() {
+        let mut wide = HAMT::<_, _, Void, Blake2b>::new();
+        let mut narrow = NarrowHAMT::<_, _, Void, Blake2b>::new();
+        for i in 0..512u32 {
+            wide.insert(i * 2, i).unwrap();
+            narrow.insert(i * 2, i).unwrap();
+        }
+        let wide_root = wide.root_hash();
+        let narrow_root = narrow.root_hash();
+
+        for i in 0..512u32 {
+            assert!(wide.prove_absent(&(i * 2)).unwrap().is_none());
+            assert!(narrow.prove_absent(&(i * 2)).unwrap().is_none());
+
+            let absent = i * 2 + 1;
+            let proof = wide.prove_absent(&absent).unwrap().unwrap();
+            assert!(HAMT::verify_absent(&proof, &absent, &wide_root));
+            // not valid for present keys, or other roots
+            assert!(!HAMT::verify_absent(&proof, &(i * 2), &wide_root));
+            assert!(!HAMT::verify_absent(&proof, &absent, &narrow_root));
+
+            let proof = narrow.prove_absent(&absent).unwrap().unwrap();
+            assert!(NarrowHAMT::verify_absent(&proof, &absent, &narrow_root));
+            assert!(!NarrowHAMT::verify_absent(&proof, &(i * 2), &narrow_root));
+        }
+
+        // absence in the empty map
+        let mut empty = HAMT::<u32, u32, Void, Blake2b>::new();
+        let proof = empty.prove_absent(&3).unwrap().unwrap();
+        assert!(HAMT::verify_absent(&proof, &3, &empty.root_hash()));
+    }
+
+    #[test]
+    fn exclusion_proofs_in_format() {
+        let v1 = Format {
+            version: EncodingVersion::V1,
+            ..Format::default()
+        };
+        let store = Store::<Blake2b>::ephemeral_with_format(v1);
+        let mut hamt = NarrowHAMT::<_, _, Void, Blake2b>::new();
+        for i in 0..512u32 {
+            hamt.insert(i.to_string(), i).unwrap();
+        }
+        let snapshot = store.persist(&mut hamt).unwrap();
+        let root = *snapshot.hash();
+
+        // a restored map holds hashes in the format of its store
+        let restored = store.restore(&snapshot).unwrap();
+        let absent = "absent".to_string();
+        assert!(restored.prove_absent(&absent).is_err());
+        let proof = restored
+            .prove_absent_with_format(&absent, v1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof.format(), v1);
+        assert!(NarrowHAMT::verify_absent(&proof, &absent, &root));
+        assert!(!NarrowHAMT::verify_absent(&proof, &"7".to_string(), &root));
+    }
+
     mod wide {
         use super::*;
         quickcheck_map!(|| CountingHAMTMap::default());
@@ -16,8 +16,8 @@ use nibbles::{AsNibbles, NibbleBuf, Nibbles};
 
 use kelvin::{
     annotations::{Annotation, Void},
-    ByteHash, Compound, Content, Handle, HandleMut, HandleType, Method,
-    SearchResult, Sink, Source, ValPath, ValPathMut,
+    ByteHash, Compound, Content, Handle, HandleMut, HandleType, HashDomain,
+    Method, SearchResult, Sink, Source, ValPath, ValPathMut,
 };
 
 const N_BUCKETS: usize = 17;
@@ -384,6 +384,10 @@ where
         }
         Ok(Radix { handles, prefixes })
     }
+
+    fn hash_domain() -> HashDomain {
+        HashDomain::Node
+    }
 }
 
 impl<K, V, A, H> Compound<H> for Radix<K, V, A, H>
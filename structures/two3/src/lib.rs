@@ -8,17 +8,19 @@
 #![warn(missing_docs)]
 
 use std::borrow::Borrow;
-use std::io;
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::{Bound, RangeBounds};
 
 use arrayvec::ArrayVec;
 
 use kelvin::{
     annotation,
     annotations::{Annotation, Cardinality, Counter, MaxKey, MaxKeyType},
-    ByteHash, Compound, Content, Handle, HandleMut, HandleType, Method,
-    SearchResult, Sink, Source, ValPath, ValPathMut, KV,
+    ByteHash, Compound, Content, Format, Handle, HandleMut, HandleRef,
+    HandleType, HashDomain, Method, SearchResult, Sink, Source, ValPath,
+    ValPathMut, Witness, KV,
 };
 
 /// The default 2-3 tree
@@ -112,6 +114,163 @@ where
     }
 }
 
+/// A proof of the exact set of entries of a `Two3Tree` within a range of
+/// keys, or of the absence of a key.
+///
+/// Holds the part of the tree overlapping the range, with the subtrees
+/// outside of it pruned to their hashes and `MaxKey` annotations. It can be
+/// persisted or transmitted as `Content`.
+///
+/// Proofs are created in the default `Format` unless given another one, and
+/// restored in the format of the store.
+#[derive(Clone)]
+pub struct RangeProof<K, V, A, H>(Two3Tree<K, V, A, H>, Format)
+where
+    H: ByteHash,
+    Two3Tree<K, V, A, H>: Compound<H>;
+
+fn invalid_proof() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid RangeProof encoding")
+}
+
+// Could a subtree with keys in `(prev, max]` hold keys in the range?
+fn overlaps<K, O, R>(range: &R, prev: Option<&K>, max: &K) -> bool
+where
+    K: Borrow<O>,
+    O: ?Sized + Ord,
+    R: RangeBounds<O>,
+{
+    let above_start = match range.start_bound() {
+        Bound::Included(start) => max.borrow() >= start,
+        Bound::Excluded(start) => max.borrow() > start,
+        Bound::Unbounded => true,
+    };
+    let below_end = match (prev, range.end_bound()) {
+        (Some(prev), Bound::Included(end))
+        | (Some(prev), Bound::Excluded(end)) => prev.borrow() < end,
+        _ => true,
+    };
+    above_start && below_end
+}
+
+impl<K, V, A, H> RangeProof<K, V, A, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    A: Annotation<KV<K, V>, H> + Borrow<MaxKey<K>>,
+    H: ByteHash,
+{
+    /// Returns the format the proof is hashed in
+    pub fn format(&self) -> Format {
+        self.1
+    }
+
+    /// Verifies the proof against the root digest of a tree, returning all
+    /// the entries with keys in the range, or None if the proof is invalid.
+    pub fn verify<O, R>(
+        &self,
+        range: R,
+        root: &H::Digest,
+    ) -> Option<Vec<KV<K, V>>>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+        R: RangeBounds<O>,
+    {
+        if self.0.clone().root_hash_with(self.1).ok()? != *root {
+            return None;
+        }
+        let mut found = vec![];
+        if Self::collect(&self.0, &range, &mut None, &mut found) {
+            Some(found)
+        } else {
+            None
+        }
+    }
+
+    /// Verifies that the proof shows the key to be absent from the tree with
+    /// the given root digest
+    pub fn verify_absent<O>(&self, k: &O, root: &H::Digest) -> bool
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+    {
+        match self.verify((Bound::Included(k), Bound::Included(k)), root) {
+            Some(found) => found.is_empty(),
+            None => false,
+        }
+    }
+
+    // Collects the entries in range in order, failing if a subtree that
+    // could hold any of them is missing from the proof
+    fn collect<O, R>(
+        node: &Two3Tree<K, V, A, H>,
+        range: &R,
+        prev: &mut Option<K>,
+        found: &mut Vec<KV<K, V>>,
+    ) -> bool
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+        R: RangeBounds<O>,
+    {
+        for child in node.0.iter() {
+            match child.inner() {
+                Ok(HandleRef::Leaf(kv)) => {
+                    if range.contains(kv.key.borrow()) {
+                        found.push(kv.clone());
+                    }
+                    *prev = Some(kv.key.clone());
+                }
+                _ => {
+                    let ann = match child.annotation() {
+                        Some(ann) => ann,
+                        None => return false,
+                    };
+                    let max: &MaxKey<K> = (*ann).borrow();
+                    if overlaps(range, prev.as_ref(), &**max) {
+                        // pruned subtrees fail to load
+                        match child.inner() {
+                            Ok(HandleRef::Node(n)) => {
+                                if !Self::collect(&n, range, prev, found) {
+                                    return false;
+                                }
+                            }
+                            _ => return false,
+                        }
+                    }
+                    *prev = Some((**max).clone());
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<K, V, A, H> Content<H> for RangeProof<K, V, A, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    A: Annotation<KV<K, V>, H> + Borrow<MaxKey<K>>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        // The pruned subtrees are known by their hash in the proof format
+        if sink.format() != self.1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Proof format mismatch",
+            ));
+        }
+        self.0.persist_partial(self.1, sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let partial = Two3Tree::restore_partial(source)?;
+        Ok(RangeProof(partial, source.format()))
+    }
+}
+
 enum InsertResult<C, H>
 where
     C: Compound<H>,
@@ -174,6 +333,173 @@ where
         ValPathMut::new(self, &mut Two3TreeSearch::from(k.borrow()))
     }
 
+    /// Creates a proof of the entries with keys in the given range, that can
+    /// be verified against the root hash of the tree
+    pub fn prove_range<O, R>(
+        &self,
+        range: R,
+    ) -> io::Result<RangeProof<K, V, A, H>>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+        R: RangeBounds<O>,
+    {
+        self.prove_range_with_format(range, Format::default())
+    }
+
+    /// Creates a proof of the entries with keys in the given range, hashing
+    /// in the given format
+    pub fn prove_range_with_format<O, R>(
+        &self,
+        range: R,
+        format: Format,
+    ) -> io::Result<RangeProof<K, V, A, H>>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+        R: RangeBounds<O>,
+    {
+        let partial = self.partial(&range, &mut None, format)?;
+        Ok(RangeProof(partial, format))
+    }
+
+    /// Creates a witness of the nodes touched by inserting the key, to be
+    /// replayed with `Witness::verify`
+    pub fn insert_witness<O>(&mut self, k: &O) -> io::Result<Witness<Self, H>>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+    {
+        Witness::new(self, Some(Two3TreeSearch::from(k)))
+    }
+
+    /// Creates a proof that the key is absent from the tree, or returns None
+    /// if the key is present
+    pub fn prove_absent<O>(
+        &self,
+        k: &O,
+    ) -> io::Result<Option<RangeProof<K, V, A, H>>>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+    {
+        self.prove_absent_with_format(k, Format::default())
+    }
+
+    /// Creates a proof that the key is absent from the tree, hashing in the
+    /// given format
+    pub fn prove_absent_with_format<O>(
+        &self,
+        k: &O,
+        format: Format,
+    ) -> io::Result<Option<RangeProof<K, V, A, H>>>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+    {
+        if self.get(k)?.is_some() {
+            return Ok(None);
+        }
+        let range = (Bound::Included(k), Bound::Included(k));
+        self.prove_range_with_format(range, format).map(Some)
+    }
+
+    // A copy of the tree with the subtrees not overlapping the range pruned
+    // to their hash in the format
+    fn partial<O, R>(
+        &self,
+        range: &R,
+        prev: &mut Option<K>,
+        format: Format,
+    ) -> io::Result<Self>
+    where
+        O: ?Sized + Ord,
+        K: Borrow<O>,
+        R: RangeBounds<O>,
+    {
+        let mut partial = Self::new();
+        for child in self.0.iter() {
+            let mut child = child.clone();
+            match child.handle_type() {
+                HandleType::None => partial.0.push(child),
+                HandleType::Leaf => {
+                    let KV { key, val: _ } = child.leaf().expect("leaf");
+                    *prev = Some(key.clone());
+                    partial.0.push(child);
+                }
+                HandleType::Node => {
+                    let ann =
+                        child.annotation().expect("node without annotation");
+                    let max: &MaxKey<K> = (*ann).borrow();
+                    let max: K = (**max).clone();
+                    if overlaps(range, prev.as_ref(), &max) {
+                        let sub = match child.inner()? {
+                            HandleRef::Node(n) => {
+                                n.partial(range, prev, format)?
+                            }
+                            _ => unreachable!(),
+                        };
+                        partial.0.push(Handle::new_node(sub));
+                    } else {
+                        partial.0.push(child.pruned_with(format)?);
+                    }
+                    *prev = Some(max);
+                }
+            }
+        }
+        Ok(partial)
+    }
+
+    // Writes a partial tree in pre-order, each node with its children
+    // pruned, followed by a mask of the children kept in the partial tree
+    fn persist_partial(
+        &mut self,
+        format: Format,
+        sink: &mut Sink<H>,
+    ) -> io::Result<()> {
+        let mut pruned = Self::new();
+        let mut kept = 0u8;
+        for (i, child) in self.0.iter_mut().enumerate() {
+            if child.handle_type() == HandleType::Node && !child.is_pruned() {
+                kept |= 1 << i;
+            }
+            pruned.0.push(child.pruned_with(format)?);
+        }
+        pruned.persist(sink)?;
+        sink.write_all(&[kept])?;
+        for (i, child) in self.0.iter_mut().enumerate() {
+            if kept & (1 << i) != 0 {
+                match child.inner_mut()? {
+                    HandleMut::Node(ref mut n) => {
+                        n.persist_partial(format, sink)?
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_partial(source: &mut Source<H>) -> io::Result<Self> {
+        let mut node = Self::restore(source)?;
+        let mut kept = [0u8];
+        source.read_exact(&mut kept)?;
+        if kept[0] >> node.0.len() != 0 {
+            return Err(invalid_proof());
+        }
+        for (i, child) in node.0.iter_mut().enumerate() {
+            *child = child.pruned_with(source.format())?;
+            if kept[0] & (1 << i) != 0 {
+                if child.handle_type() != HandleType::Node {
+                    return Err(invalid_proof());
+                }
+                let sub = source.nested(Self::restore_partial)?;
+                *child = Handle::new_node(sub);
+            }
+        }
+        Ok(node)
+    }
+
     fn _insert(
         &mut self,
         mut handle: Handle<Self, H>,
@@ -488,11 +814,21 @@ where
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
         let mut b = Two3Tree::default();
         let len = u8::restore(source)?;
+        if len as usize > M {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid Two3Tree encoding",
+            ));
+        }
         for _ in 0..len {
             b.0.push(Handle::restore(source)?);
         }
         Ok(b)
     }
+
+    fn hash_domain() -> HashDomain {
+        HashDomain::Node
+    }
 }
 
 impl<K, V, A, H> Compound<H> for Two3Tree<K, V, A, H>
@@ -623,6 +959,149 @@ mod test {
         }
     }
 
+    #[test]
+    fn range_proofs() {
+        let mut h = Two3Tree::<_, _, MaxKey<_>, Blake2b>::new();
+        for i in 0..1024u32 {
+            h.insert(i * 2, i).unwrap();
+        }
+        let root = h.root_hash();
+
+        for &(a, b) in
+            &[(0, 0), (0, 10), (101, 300), (1000, 4000), (3000, 4000)]
+        {
+            let proof = h.prove_range(a..b).unwrap();
+            let found = proof.verify(a..b, &root).unwrap();
+            let keys: Vec<u32> = found.iter().map(|kv| kv.key).collect();
+            let expected: Vec<u32> =
+                (a..b).filter(|k| k % 2 == 0 && *k < 2048).collect();
+            assert_eq!(keys, expected);
+
+            // the proof does not cover a wider range
+            if b < 2048 {
+                assert!(proof.verify(a..b + 100, &root).is_none());
+            }
+        }
+
+        let proof = h.prove_range(..).unwrap();
+        assert_eq!(proof.verify(.., &root).unwrap().len(), 1024);
+
+        // wrong root
+        let mut other = h.clone();
+        other.insert(5, 5).unwrap();
+        let proof = h.prove_range(0..10).unwrap();
+        assert!(proof.verify(0..10, &other.root_hash()).is_none());
+    }
+
+    #[test]
+    fn range_proofs_in_format() {
+        let separated = Format {
+            domain_separation: true,
+            ..Format::default()
+        };
+        let store = kelvin::Store::<Blake2b>::ephemeral_with_format(separated);
+        let mut h = Two3Tree::<_, _, MaxKey<_>, Blake2b>::new();
+        for i in 0..1024u32 {
+            h.insert(i * 2, i).unwrap();
+        }
+        let root = h.root_hash_with(separated).unwrap();
+        let keys = |found: Vec<KV<u32, u32>>| -> Vec<u32> {
+            found.iter().map(|kv| kv.key).collect()
+        };
+        let expected: Vec<u32> = (100..300).filter(|k| k % 2 == 0).collect();
+
+        let mut proof = h.prove_range_with_format(100..300, separated).unwrap();
+        assert_eq!(proof.format(), separated);
+        assert_eq!(keys(proof.verify(100..300, &root).unwrap()), expected);
+
+        // proofs in another format neither verify nor persist
+        let mut other = h.prove_range(100..300).unwrap();
+        assert!(other.verify(100..300, &root).is_none());
+        assert!(store.persist(&mut other).is_err());
+
+        let snapshot = store.persist(&mut proof).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.format(), separated);
+        assert_eq!(keys(restored.verify(100..300, &root).unwrap()), expected);
+        assert!(restored.verify(100..400, &root).is_none());
+
+        // a restored tree holds hashes in the format of its store
+        let tree = store.persist(&mut h.clone()).unwrap();
+        let restored = store.restore(&tree).unwrap();
+        assert!(restored.prove_range(0..10).is_err());
+        let proof = restored
+            .prove_absent_with_format(&7, separated)
+            .unwrap()
+            .unwrap();
+        assert!(proof.verify_absent(&7, &root));
+    }
+
+    #[test]
+    fn exclusion_proofs() {
+        let mut h = Two3Tree::<_, _, MaxKey<_>, Blake2b>::new();
+        for i in 0..512u32 {
+            h.insert(i * 2, i).unwrap();
+        }
+        let root = h.root_hash();
+
+        for i in 0..512u32 {
+            assert!(h.prove_absent(&(i * 2)).unwrap().is_none());
+
+            let absent = i * 2 + 1;
+            let proof = h.prove_absent(&absent).unwrap().unwrap();
+            assert!(proof.verify_absent(&absent, &root));
+            assert!(!proof.verify_absent(&(i * 2), &root));
+        }
+
+        // beyond the greatest key
+        let proof = h.prove_absent(&5000).unwrap().unwrap();
+        assert!(proof.verify_absent(&5000, &root));
+
+        let mut empty = Two3Tree::<u32, u32, MaxKey<_>, Blake2b>::new();
+        let proof = empty.prove_absent(&3).unwrap().unwrap();
+        assert!(proof.verify_absent(&3, &empty.root_hash()));
+    }
+
+    #[test]
+    fn insert_witness() {
+        let mut h = Two3Tree::<_, _, MaxKey<_>, Blake2b>::new();
+        for i in 0..1024u32 {
+            h.insert(i * 2, i).unwrap();
+        }
+
+        for &key in &[0, 7, 100, 1025, 5000] {
+            let before = h.root_hash();
+            let witness = h.insert_witness(&key).unwrap();
+            h.insert(key, 1).unwrap();
+            let after = h.root_hash();
+
+            let replaced = if key % 2 == 0 && key < 2048 {
+                Some(key / 2)
+            } else {
+                None
+            };
+            assert_eq!(
+                witness.verify(&before, &after, |tree| tree.insert(key, 1)),
+                Some(replaced)
+            );
+            assert!(witness
+                .verify(&before, &after, |tree| tree.insert(key + 1, 1))
+                .is_none());
+        }
+
+        // splitting up to the root
+        let mut h = Two3Tree::<_, _, MaxKey<_>, Blake2b>::new();
+        for i in 0..27u32 {
+            h.insert(i, i).unwrap();
+        }
+        let before = h.root_hash();
+        let witness = h.insert_witness(&27).unwrap();
+        h.insert(27, 27).unwrap();
+        assert!(witness
+            .verify(&before, &h.root_hash(), |tree| tree.insert(27, 27))
+            .is_some());
+    }
+
     quickcheck_map!(|| {
         Two3Tree::<_, _, Two3TreeAnnotation<_, u64>, Blake2b>::new()
     });
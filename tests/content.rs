@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use kelvin::{Blake2b, Content, EncodingVersion, Format, Store, Varint};
+
+fn roundtrip<T: Content<Blake2b> + PartialEq + Debug>(mut t: T) {
+    let store = Store::<Blake2b>::ephemeral();
+    let snapshot = store.persist(&mut t).unwrap();
+    assert_eq!(store.restore(&snapshot).unwrap(), t);
+}
+
+fn digest<T: Content<Blake2b>>(mut t: T) -> Vec<u8> {
+    let store = Store::<Blake2b>::ephemeral();
+    store.persist(&mut t).unwrap().as_ref().to_vec()
+}
+
+#[test]
+fn primitives() {
+    roundtrip(-3i8);
+    roundtrip('ö');
+    roundtrip(usize::max_value());
+    roundtrip(isize::min_value());
+    roundtrip::<Result<u8, String>>(Ok(3));
+    roundtrip::<Result<u8, String>>(Err("oops".into()));
+    assert_eq!(digest(7usize), digest(7u64));
+}
+
+#[test]
+fn invalid_char() {
+    let store = Store::<Blake2b>::ephemeral();
+    let snapshot = store.persist(&mut 0xd800u32).unwrap();
+    let err = store.snapshot_from_digest::<char>(&snapshot).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn tuples_and_arrays() {
+    roundtrip((1u8,));
+    roundtrip((1u8, 2u16, 3u32, 4u64, 5u128, 6i8, 7i16, 8i32, 9i64, 10i128));
+    roundtrip((
+        1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8,
+    ));
+    roundtrip([7u32; 33]);
+    roundtrip([1u8; 100]);
+    assert_eq!(digest([1u8, 2]), digest((1u8, 2u8)));
+}
+
+#[test]
+fn pointers() {
+    roundtrip(Rc::new(3u8));
+    roundtrip(Arc::new(String::from("shared")));
+    roundtrip::<Cow<'static, str>>(Cow::Borrowed("borrowed"));
+    assert_eq!(
+        digest::<Cow<'static, str>>(Cow::Borrowed("same")),
+        digest(String::from("same"))
+    );
+}
+
+#[test]
+fn collections() {
+    roundtrip((0..10u32).collect::<VecDeque<_>>());
+    roundtrip((0..10u32).map(|i| (i, i * 2)).collect::<BTreeMap<_, _>>());
+    roundtrip((0..10u32).collect::<BTreeSet<_>>());
+    roundtrip((0..10u32).map(|i| (i, i * 2)).collect::<HashMap<_, _>>());
+    roundtrip((0..10u32).collect::<HashSet<_>>());
+
+    assert_eq!(
+        digest((0..10u32).collect::<VecDeque<_>>()),
+        digest((0..10u32).collect::<Vec<_>>())
+    );
+}
+
+#[test]
+fn sorted_encoding() {
+    let forward: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+    let backward: HashMap<u32, u32> = (0..100).rev().map(|i| (i, i)).collect();
+    let btree: BTreeMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+
+    assert_eq!(digest(forward.clone()), digest(backward));
+    assert_eq!(digest(forward), digest(btree));
+
+    let set: HashSet<u32> = (0..100).collect();
+    let btree: BTreeSet<u32> = (0..100).rev().collect();
+    assert_eq!(digest(set), digest(btree));
+}
+
+#[test]
+fn reject_unsorted() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let unsorted = store.persist(&mut vec![(2u8, 0u8), (1, 0)]).unwrap();
+    let duplicate = store.persist(&mut vec![(1u8, 0u8), (1, 0)]).unwrap();
+
+    for snapshot in &[unsorted, duplicate] {
+        let err = store
+            .snapshot_from_digest::<BTreeMap<u8, u8>>(snapshot)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = store
+            .snapshot_from_digest::<HashMap<u8, u8>>(snapshot)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn length_prefix() {
+    assert_eq!(digest(vec![1u8, 2, 3]), digest((3u64, [1u8, 2, 3])));
+
+    let store = Store::<Blake2b>::ephemeral_with_format(Format {
+        version: EncodingVersion::V1,
+        ..Format::default()
+    });
+    let mut bytes = vec![1u8, 2, 3];
+    let snapshot = store.persist(&mut bytes).unwrap();
+    let expected = store.persist(&mut (Varint(3u64), [1u8, 2, 3])).unwrap();
+    assert_eq!(snapshot.hash(), expected.hash());
+    assert_eq!(store.restore(&snapshot).unwrap(), bytes);
+}
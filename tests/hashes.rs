@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use kelvin::{
+    Blake2b, Blake3, ByteHash, ByteHashState, Compound, HashName, Keccak256,
+    Sha256, SnapshotId, Store, Void,
+};
+use kelvin_hamt::HAMT;
+
+fn hex<H: HashName>(digest: H::Digest) -> String {
+    let id = SnapshotId::<H>::new(digest).to_string();
+    id[H::NAME.len() + 1..].into()
+}
+
+fn root_hash<H: ByteHash>() -> H::Digest {
+    let mut hamt = HAMT::<_, _, Void, H>::new();
+    for i in 0..256u32 {
+        hamt.insert(i, i).unwrap();
+    }
+    hamt.root_hash()
+}
+
+#[test]
+fn known_digests() {
+    // digest of the empty string
+    fn empty<H: ByteHash>() -> H::Digest {
+        H::state().fin()
+    }
+
+    assert_eq!(
+        hex::<Sha256>(empty::<Sha256>()),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex::<Keccak256>(empty::<Keccak256>()),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    assert_eq!(
+        hex::<Blake3>(empty::<Blake3>()),
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+    );
+}
+
+#[test]
+fn cross_hasher_roots() {
+    let roots = vec![
+        hex::<Blake2b>(root_hash::<Blake2b>()),
+        hex::<Sha256>(root_hash::<Sha256>()),
+        hex::<Keccak256>(root_hash::<Keccak256>()),
+        hex::<Blake3>(root_hash::<Blake3>()),
+    ];
+
+    // distinct between hashers
+    for (i, a) in roots.iter().enumerate() {
+        for b in &roots[i + 1..] {
+            assert!(a != b);
+        }
+    }
+
+    // and stable for each
+    assert_eq!(roots[1], hex::<Sha256>(root_hash::<Sha256>()));
+    assert_eq!(roots[2], hex::<Keccak256>(root_hash::<Keccak256>()));
+    assert_eq!(roots[3], hex::<Blake3>(root_hash::<Blake3>()));
+}
+
+#[test]
+fn persist_and_restore() {
+    fn roundtrip<H: ByteHash>() {
+        let store = Store::<H>::ephemeral();
+        let mut hamt = HAMT::<_, _, Void, H>::new();
+        for i in 0..256u32 {
+            hamt.insert(i, i * 2).unwrap();
+        }
+        let snapshot = store.persist(&mut hamt).unwrap();
+        assert_eq!(snapshot.hash(), &hamt.root_hash());
+
+        let restored = store.restore(&snapshot).unwrap();
+        for i in 0..256u32 {
+            assert_eq!(*restored.get(&i).unwrap().unwrap(), i * 2);
+        }
+    }
+
+    roundtrip::<Sha256>();
+    roundtrip::<Keccak256>();
+    roundtrip::<Blake3>();
+}
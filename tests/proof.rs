@@ -4,7 +4,11 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use kelvin::{Blake2b, Compound, Void, KV};
+use std::io;
+
+use kelvin::{
+    Blake2b, Compound, Content, EncodingVersion, Sink, Source, Varint, Void, KV,
+};
 use kelvin_hamt::{HAMTSearch, NarrowHAMT};
 
 #[test]
@@ -42,3 +46,352 @@ fn merkle_proof() {
         assert_eq!(proof.prove_member(&mut cloned), None);
     }
 }
+
+#[test]
+fn persisted_proof() {
+    use kelvin::{Proof, Store};
+
+    let store = Store::<Blake2b>::ephemeral();
+    let mut hamt = NarrowHAMT::<_, _, Void, Blake2b>::new();
+
+    for i in 0..1024u32 {
+        hamt.insert(i, i).unwrap();
+    }
+
+    let mut proof = {
+        let mut branch =
+            hamt.search_mut(&mut HAMTSearch::from(&7)).unwrap().unwrap();
+        Proof::new(&mut branch)
+    };
+
+    let snapshot = store.persist(&mut proof).unwrap();
+    let mut restored = store.restore(&snapshot).unwrap();
+
+    assert_eq!(
+        restored.prove_member(&mut hamt),
+        Some(&KV { key: 7, val: 7 })
+    );
+
+    // only the nodes along the path are stored
+    let mut full = hamt.clone();
+    let size = store.size();
+    store.persist(&mut full).unwrap();
+    assert!(size * 4 < store.size() - size);
+
+    hamt.insert(3, 8).unwrap();
+    assert_eq!(restored.prove_member(&mut hamt), None);
+}
+
+#[test]
+fn proof_in_store_format() {
+    use kelvin::{Format, Proof, Store};
+
+    let v1 = Format {
+        version: EncodingVersion::V1,
+        ..Format::default()
+    };
+    let store = Store::<Blake2b>::ephemeral_with_format(v1);
+    let mut hamt = NarrowHAMT::<String, u32, Void, Blake2b>::new();
+
+    for i in 0..256u32 {
+        hamt.insert(i.to_string(), i).unwrap();
+    }
+
+    // string keys are length prefixed, so the hash depends on the format
+    let root = hamt.root_hash_with(v1).unwrap();
+    assert!(root != hamt.root_hash());
+    let tree = store.persist(&mut hamt.clone()).unwrap();
+    assert_eq!(tree.hash(), &root);
+
+    let key = "7".to_string();
+    let mut branch = hamt
+        .search_mut(&mut HAMTSearch::from(&key))
+        .unwrap()
+        .unwrap();
+    let mut proof = Proof::new_with_format(&mut branch, v1).unwrap();
+    let mut other = Proof::new(&mut branch);
+    drop(branch);
+
+    assert!(proof.verify(&root).is_some());
+    assert!(other.verify(&root).is_none());
+    assert!(store.persist(&mut other).is_err());
+
+    let snapshot = store.persist(&mut proof).unwrap();
+    let restored = store.restore(&snapshot).unwrap();
+    assert_eq!(restored.format(), v1);
+    assert_eq!(
+        restored.verify(&root),
+        Some(&KV {
+            key: key.clone(),
+            val: 7
+        })
+    );
+
+    // nodes restored from the store are known by their hash in its format
+    let mut restored = store.restore(&tree).unwrap();
+    assert_eq!(restored.root_hash_with(v1).unwrap(), root);
+    let err = restored.root_hash_with(Format::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let mut branch = restored
+        .search_mut(&mut HAMTSearch::from(&key))
+        .unwrap()
+        .unwrap();
+    assert!(Proof::new_with_format(&mut branch, Format::default()).is_err());
+    let proof = Proof::new_with_format(&mut branch, v1).unwrap();
+    assert!(proof.verify(&root).is_some());
+}
+
+#[test]
+fn verify_against_digest() {
+    use kelvin::Proof;
+
+    let mut hamt = NarrowHAMT::<_, _, Void, Blake2b>::new();
+
+    for i in 0..256u32 {
+        hamt.insert(i, i * 2).unwrap();
+    }
+
+    // the digest from a block header
+    let root = hamt.root_hash();
+
+    let proof = {
+        let mut branch = hamt
+            .search_mut(&mut HAMTSearch::from(&42))
+            .unwrap()
+            .unwrap();
+        Proof::new(&mut branch)
+    };
+
+    assert_eq!(proof.verify(&root), Some(&KV { key: 42, val: 84 }));
+    assert_eq!(proof.verify(&Default::default()), None);
+
+    hamt.insert(1000, 0).unwrap();
+    assert_eq!(proof.verify(&hamt.root_hash()), None);
+}
+
+#[test]
+fn multi_proof() {
+    use kelvin::{MultiProof, Proof, Store};
+
+    let store = Store::<Blake2b>::ephemeral();
+    let mut hamt = NarrowHAMT::<_, _, Void, Blake2b>::new();
+
+    for i in 0..1024u32 {
+        hamt.insert(i, i * 2).unwrap();
+    }
+    let root = hamt.root_hash();
+
+    let keys: Vec<u32> = (0..16).map(|i| i * 61).collect();
+    let mut proof =
+        MultiProof::new(&mut hamt, keys.iter().map(HAMTSearch::from))
+            .unwrap()
+            .unwrap();
+
+    let expected: Vec<_> = keys
+        .iter()
+        .map(|k| KV {
+            key: *k,
+            val: k * 2,
+        })
+        .collect();
+    let proven: Vec<_> = proof.verify(&root).unwrap();
+    assert_eq!(proven, expected.iter().collect::<Vec<_>>());
+
+    assert!(proof.verify(&Default::default()).is_none());
+
+    // all searches must find a leaf
+    assert!(MultiProof::new(&mut hamt, vec![HAMTSearch::from(&2000)])
+        .unwrap()
+        .is_none());
+
+    // smaller than the separate proofs
+    let size = store.size();
+    let snapshot = store.persist(&mut proof).unwrap();
+    let multi_size = store.size() - size;
+    let size = store.size();
+    for key in &keys {
+        let mut branch = hamt
+            .search_mut(&mut HAMTSearch::from(key))
+            .unwrap()
+            .unwrap();
+        store.persist(&mut Proof::new(&mut branch)).unwrap();
+    }
+    assert!(multi_size < store.size() - size);
+
+    let restored: MultiProof<_, _> = store.restore(&snapshot).unwrap();
+    assert_eq!(restored.verify(&root).unwrap().len(), keys.len());
+
+    hamt.insert(3000, 0).unwrap();
+    assert!(restored.verify(&hamt.root_hash()).is_none());
+}
+
+#[test]
+fn annotation_proof() {
+    use kelvin::annotations::Cardinality;
+    use kelvin::{AnnotationProof, Store};
+    use kelvin_hamt::HAMT;
+
+    let store = Store::<Blake2b>::ephemeral();
+    let mut hamt = HAMT::<_, _, Cardinality<u64>, Blake2b>::new();
+
+    for i in 0..1000u32 {
+        hamt.insert(i, i).unwrap();
+    }
+    let root = hamt.root_hash();
+
+    let mut proof = AnnotationProof::new(&mut hamt);
+    assert_eq!(*proof.verify(&root).unwrap(), 1000);
+    assert!(proof.verify(&Default::default()).is_none());
+
+    // only the root node is shipped
+    let size = store.size();
+    let snapshot = store.persist(&mut proof).unwrap();
+    let proof_size = store.size() - size;
+    let size = store.size();
+    store.persist(&mut hamt.clone()).unwrap();
+    assert!(proof_size * 16 < store.size() - size);
+
+    let restored: AnnotationProof<_, _> = store.restore(&snapshot).unwrap();
+    assert_eq!(*restored.verify(&root).unwrap(), 1000);
+
+    hamt.remove(&7).unwrap();
+    assert!(restored.verify(&hamt.root_hash()).is_none());
+    let proof = AnnotationProof::new(&mut hamt);
+    assert_eq!(*proof.verify(&hamt.root_hash()).unwrap(), 999);
+}
+
+#[test]
+fn insert_witness() {
+    use kelvin::{Store, Witness};
+
+    let store = Store::<Blake2b>::ephemeral();
+    let mut hamt = NarrowHAMT::<_, _, Void, Blake2b>::new();
+
+    for i in 0..1024u32 {
+        hamt.insert(i * 2, i).unwrap();
+    }
+
+    for &key in &[0, 7, 100, 1025, 5000] {
+        let before = hamt.root_hash();
+        let mut witness = hamt.insert_witness(&key).unwrap();
+        hamt.insert(key, 1).unwrap();
+        let after = hamt.root_hash();
+
+        let replaced = if key % 2 == 0 && key < 2048 {
+            Some(key / 2)
+        } else {
+            None
+        };
+        assert_eq!(
+            witness.verify(&before, &after, |map| map.insert(key, 1)),
+            Some(replaced)
+        );
+        // different operations, or roots, fail
+        assert!(witness
+            .verify(&before, &after, |map| map.insert(key, 2))
+            .is_none());
+        assert!(witness
+            .verify(&after, &after, |map| map.insert(key, 1))
+            .is_none());
+
+        let snapshot = store.persist(&mut witness).unwrap();
+        let restored: Witness<_, _> = store.restore(&snapshot).unwrap();
+        assert!(restored
+            .verify(&before, &after, |map| map.insert(key, 1))
+            .is_some());
+    }
+
+    // operations leaving the witness fail
+    let witness = hamt.insert_witness(&3).unwrap();
+    let root = hamt.root_hash();
+    assert!(witness
+        .verify(&root, &root, |map| map.get(&4).map(|_| ()))
+        .is_none());
+}
+
+fn write_len(sink: &mut Sink<Blake2b>, mut len: u64) -> io::Result<()> {
+    match sink.format().version {
+        EncodingVersion::V0 => len.persist(sink),
+        EncodingVersion::V1 => Varint(len).persist(sink),
+    }
+}
+
+// A proof encoding with a single path descending `depth` levels through
+// empty nodes
+#[derive(Clone)]
+struct DeepPath(u64);
+
+impl Content<Blake2b> for DeepPath {
+    fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+        write_len(sink, 1)?;
+        write_len(sink, self.0)?;
+        for _ in 0..self.0 {
+            write_len(sink, 0)?;
+        }
+        write_len(sink, self.0)?;
+        for _ in 0..self.0 {
+            NarrowHAMT::<u32, u32, Void, Blake2b>::new().persist(sink)?;
+        }
+        Ok(())
+    }
+
+    // Only ever restored as a proof
+    fn restore(_: &mut Source<Blake2b>) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "DeepPath is not restorable",
+        ))
+    }
+}
+
+#[test]
+fn deep_multi_proof() {
+    use kelvin::{Limits, MultiProof, Store};
+
+    type Proof = MultiProof<NarrowHAMT<u32, u32, Void, Blake2b>, Blake2b>;
+
+    let store = Store::<Blake2b>::ephemeral();
+    store.set_limits(Limits::unbounded());
+    let deep = store.persist(&mut DeepPath(100_000)).unwrap();
+    let shallow = store.persist(&mut DeepPath(100)).unwrap();
+
+    // fails to verify without exhausting the stack
+    let proof = store.snapshot_from_digest::<Proof>(&deep).unwrap();
+    assert!(store.restore(&proof).unwrap().verify(&[0; 32]).is_none());
+
+    store.set_limits(Limits::untrusted());
+    assert!(store.snapshot_from_digest::<Proof>(&deep).is_err());
+    assert!(store.snapshot_from_digest::<Proof>(&shallow).is_ok());
+}
+
+#[test]
+fn deep_witness() {
+    use kelvin::{Limits, Store, Witness};
+
+    type Map = NarrowHAMT<u32, u32, Void, Blake2b>;
+
+    let store = Store::<Blake2b>::ephemeral();
+    store.set_limits(Limits::unbounded());
+    let deep = store.persist(&mut DeepPath(100_000)).unwrap();
+    let shallow = store.persist(&mut DeepPath(100)).unwrap();
+
+    // fails to rebuild without exhausting the stack
+    let witness = store
+        .snapshot_from_digest::<Witness<Map, Blake2b>>(&deep)
+        .unwrap();
+    let root = Map::new().root_hash();
+    assert!(store
+        .restore(&witness)
+        .unwrap()
+        .verify(&root, &root, |_| Ok(()))
+        .is_none());
+
+    store.set_limits(Limits::untrusted());
+    assert!(store
+        .snapshot_from_digest::<Witness<Map, Blake2b>>(&deep)
+        .is_err());
+    assert!(store
+        .snapshot_from_digest::<Witness<Map, Blake2b>>(&shallow)
+        .is_ok());
+}
@@ -0,0 +1,98 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use kelvin::annotations::{Cardinality, Count};
+use kelvin::{
+    Blake2b, Compound, Handle, HandleRef, LeafIterable, PrunedError, Store,
+};
+use kelvin_hamt::HAMT;
+
+type Map = HAMT<u32, u32, Cardinality<u64>, Blake2b>;
+
+fn map() -> Map {
+    let mut hamt = Map::new();
+    for i in 0..1024 {
+        hamt.insert(i, i).unwrap();
+    }
+    hamt
+}
+
+#[test]
+fn prune_all() {
+    let mut hamt = map();
+    let root = hamt.root_hash();
+
+    let mut pruned = hamt.clone();
+    for child in pruned.children_mut() {
+        *child = child.pruned();
+        assert!(child.is_pruned());
+    }
+
+    // hashing and annotations still work
+    assert_eq!(pruned.root_hash(), root);
+    assert_eq!(pruned.count(), 1024);
+
+    let err = pruned.get(&7).err().expect("pruned");
+    assert!(PrunedError::matches(&err));
+    assert!(pruned.iter().any(|leaf| leaf.is_err()));
+}
+
+#[test]
+fn sparse_view() {
+    let mut hamt = map();
+    let root = hamt.root_hash();
+
+    // keep only the first child of the root
+    let mut sparse = hamt.clone();
+    for child in sparse.children_mut().iter_mut().skip(1) {
+        *child = child.pruned();
+    }
+    assert_eq!(sparse.root_hash(), root);
+
+    let mut found = 0;
+    for i in 0..1024 {
+        match sparse.get(&i) {
+            Ok(value) => {
+                assert_eq!(*value.unwrap(), i);
+                found += 1;
+            }
+            Err(err) => assert!(PrunedError::matches(&err)),
+        }
+    }
+    assert!(found > 0 && found < 1024);
+
+    // modifications in the retained region update the root
+    let mut i = 0;
+    while sparse.get(&i).is_err() {
+        i += 1;
+    }
+    sparse.insert(i, 0).unwrap();
+    hamt.insert(i, 0).unwrap();
+    assert_eq!(sparse.root_hash(), hamt.root_hash());
+}
+
+#[test]
+fn pruned_from_digest() {
+    let store = Store::<Blake2b>::ephemeral();
+    let mut hamt = map();
+    let root = hamt.root_hash();
+
+    // a light client only knows the digests and annotations of the children
+    let mut light = Map::new();
+    for (child, light_child) in hamt.children().iter().zip(light.children_mut())
+    {
+        let ann = child.annotation().expect("full root").into_owned();
+        let mut node = match child.inner().unwrap() {
+            HandleRef::Node(node) => (*node).clone(),
+            _ => panic!("expected node"),
+        };
+        let digest = *store.persist(&mut node).unwrap().hash();
+        *light_child = Handle::new_pruned(digest, ann);
+    }
+
+    assert_eq!(light.root_hash(), root);
+    assert_eq!(light.count(), 1024);
+}
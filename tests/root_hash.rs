@@ -4,8 +4,13 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use kelvin::{Blake2b, Compound, Store, Void};
+use kelvin::{
+    Blake2b, ByteHash, Compound, Content, Format, Sink, Source, Store, Void,
+};
 use kelvin_hamt::HAMT;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
 
 #[test]
 fn root_hash() {
@@ -31,3 +36,85 @@ fn root_hash() {
 
     assert_eq!(root_hash, restored_root_hash);
 }
+
+// Reads and writes the raw bytes of a node
+#[derive(Clone)]
+struct Raw(Vec<u8>);
+
+impl<H: ByteHash> Content<H> for Raw {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_all(&self.0)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut bytes = vec![];
+        source.read_to_end(&mut bytes)?;
+        Ok(Raw(bytes))
+    }
+}
+
+#[test]
+fn domain_separation() {
+    let separated = Format {
+        domain_separation: true,
+        ..Format::default()
+    };
+
+    for format in [Format::default(), separated].iter() {
+        let store = Store::<Blake2b>::ephemeral_with_format(*format);
+
+        let mut hamt = HAMT::<_, _, Void, Blake2b>::new();
+        hamt.insert(1u32, 2u32).unwrap();
+        let node = store.persist(&mut hamt).unwrap();
+        assert_eq!(node.hash(), &hamt.root_hash_with(*format).unwrap());
+
+        // a leaf value with the same bytes as the node
+        let mut raw = store
+            .restore(&store.snapshot_from_digest::<Raw>(node.hash()).unwrap())
+            .unwrap();
+        let leaf = store.persist(&mut raw).unwrap();
+
+        assert_eq!(leaf.hash() == node.hash(), !format.domain_separation);
+    }
+}
+
+#[test]
+fn empty_node_domain() {
+    let separated = Format {
+        domain_separation: true,
+        ..Format::default()
+    };
+    let store = Store::<Blake2b>::ephemeral_with_format(separated);
+
+    // an empty node writes no child handles, but is still a tree node
+    let mut hamt = HAMT::<u32, u32, Void, Blake2b>::new();
+    let node = store.persist(&mut hamt).unwrap();
+    assert_eq!(node.hash(), &hamt.root_hash_with(separated).unwrap());
+
+    let mut raw = store
+        .restore(&store.snapshot_from_digest::<Raw>(node.hash()).unwrap())
+        .unwrap();
+    let leaf = store.persist(&mut raw).unwrap();
+    assert!(leaf.hash() != node.hash());
+}
+
+#[test]
+fn pointer_domain() {
+    let separated = Format {
+        domain_separation: true,
+        ..Format::default()
+    };
+    let store = Store::<Blake2b>::ephemeral_with_format(separated);
+
+    let mut hamt = HAMT::<u32, u32, Void, Blake2b>::new();
+    hamt.insert(1, 2).unwrap();
+    let root = hamt.root_hash_with(separated).unwrap();
+
+    // pointers hash in the domain of what they point to
+    let rc = store.persist(&mut Rc::new(hamt.clone())).unwrap();
+    let arc = store.persist(&mut Arc::new(hamt.clone())).unwrap();
+    let boxed = store.persist(&mut Box::new(hamt)).unwrap();
+    for snapshot in [rc.hash(), arc.hash(), boxed.hash()].iter() {
+        assert_eq!(*snapshot, &root);
+    }
+}
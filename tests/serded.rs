@@ -0,0 +1,158 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::io;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+
+use kelvin::{
+    Blake2b, Content, EncodingVersion, Format, Serded, Store, Varint,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Empty,
+    Circle(u32),
+    Rect { w: u16, h: u16 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Account {
+    name: String,
+    balance: u64,
+    limit: Option<i32>,
+    ratio: f64,
+    shapes: Vec<Shape>,
+    tags: HashMap<String, (u8, char)>,
+}
+
+fn roundtrip<T>(t: T)
+where
+    T: Serialize + DeserializeOwned + Clone + PartialEq + Debug + 'static,
+{
+    let store = Store::<Blake2b>::ephemeral();
+    let snapshot = store.persist(&mut Serded(t.clone())).unwrap();
+    assert_eq!(store.restore(&snapshot).unwrap(), Serded(t));
+}
+
+fn digest<T: Content<Blake2b>>(mut t: T) -> Vec<u8> {
+    let store = Store::<Blake2b>::ephemeral();
+    store.persist(&mut t).unwrap().as_ref().to_vec()
+}
+
+#[test]
+fn roundtrips() {
+    let mut tags = HashMap::new();
+    tags.insert("a".to_string(), (1, 'a'));
+    tags.insert("ö".to_string(), (2, 'ö'));
+
+    roundtrip(Account {
+        name: "alice".into(),
+        balance: 1_000_000,
+        limit: Some(-30),
+        ratio: 0.25,
+        shapes: vec![
+            Shape::Empty,
+            Shape::Circle(3),
+            Shape::Rect { w: 1, h: 2 },
+        ],
+        tags,
+    });
+    roundtrip(Shape::Empty);
+    roundtrip(());
+    roundtrip(vec![Some(1u8), None]);
+}
+
+#[test]
+fn stable_bytes() {
+    let value = (Shape::Rect { w: 0x0102, h: 3 }, Some(true), -1i8, 'a');
+    let expected = [
+        0u8, 0, 0, 2, // variant index
+        1, 2, // w
+        0, 3, // h
+        1, 1,    // Some(true)
+        0xff, // -1
+        0, 0, 0, 0x61, // 'a'
+    ];
+    assert_eq!(digest(Serded(value)), digest(expected));
+}
+
+#[test]
+fn map_ordering() {
+    let forward: HashMap<u32, String> =
+        (0..100).map(|i| (i, i.to_string())).collect();
+    let backward: HashMap<u32, String> =
+        (0..100).rev().map(|i| (i, i.to_string())).collect();
+    let btree: BTreeMap<u32, String> =
+        (0..100).map(|i| (i, i.to_string())).collect();
+
+    assert_eq!(digest(Serded(forward.clone())), digest(Serded(backward)));
+    assert_eq!(digest(Serded(forward)), digest(Serded(btree)));
+}
+
+#[test]
+fn reject_non_canonical() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let v1 = Store::<Blake2b>::ephemeral_with_format(Format {
+        version: EncodingVersion::V1,
+        ..Format::default()
+    });
+
+    // map entries out of order, in either encoding version
+    let unsorted = store
+        .persist(&mut (2u64, [2u8, 0, 1, 0]))
+        .unwrap()
+        .into_hash();
+    let unsorted_v1 = v1
+        .persist(&mut (Varint(2u64), [2u8, 0, 1, 0]))
+        .unwrap()
+        .into_hash();
+    for (store, unsorted) in [(&store, unsorted), (&v1, unsorted_v1)].iter() {
+        let err = store
+            .snapshot_from_digest::<Serded<BTreeMap<u8, u8>>>(unsorted)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // NaN with a non-canonical payload
+    let nan = store.persist(&mut 0x7ff8_0000_0000_0001u64).unwrap();
+    let err = store.snapshot_from_digest::<Serded<f64>>(&nan).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // negative zero
+    let zero = store.persist(&mut 0x8000_0000_0000_0000u64).unwrap();
+    let err = store
+        .snapshot_from_digest::<Serded<f64>>(&zero)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn negative_zero() {
+    assert_eq!(digest(Serded(-0.0f64)), digest(Serded(0.0f64)));
+    assert_eq!(digest(Serded(-0.0f32)), digest(Serded(0.0f32)));
+}
+
+// Serializes as a sequence of unknown length
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct Evens(Vec<u32>);
+
+impl Serialize for Evens {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(self.0.iter().filter(|i| *i % 2 == 0))
+    }
+}
+
+#[test]
+fn unknown_length_sequences() {
+    let evens = Evens((0..10).collect());
+    let nested = vec![evens.clone(), Evens(vec![]), evens];
+    let expected = vec![vec![0u32, 2, 4, 6, 8], vec![], vec![0, 2, 4, 6, 8]];
+    assert_eq!(digest(Serded(nested)), digest(Serded(expected)));
+}
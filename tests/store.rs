@@ -4,8 +4,15 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use kelvin::{Blake2b, Store};
+use kelvin::{
+    Blake2b, ByteHash, Content, EncodingVersion, Format, Limits, Link, Sink,
+    SnapshotId, Source, Store, Varint,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
 use tempfile::tempdir;
 
 #[test]
@@ -29,3 +36,295 @@ fn should_allow_two() {
     }
     let _store = Store::<Blake2b>::new(dir.path()).unwrap();
 }
+
+#[test]
+fn recorded_format() {
+    let v1 = Format {
+        version: EncodingVersion::V1,
+        ..Format::default()
+    };
+
+    let dir = tempdir().unwrap();
+    {
+        let store = Store::<Blake2b>::new_with_format(dir.path(), v1).unwrap();
+        assert_eq!(store.format(), v1);
+    }
+
+    // reopened in the recorded format
+    let store = Store::<Blake2b>::new(dir.path()).unwrap();
+    assert_eq!(store.format(), v1);
+    drop(store);
+    let store = Store::<Blake2b>::new_with_format(dir.path(), v1).unwrap();
+    assert_eq!(store.format(), v1);
+    drop(store);
+
+    let err = Store::<Blake2b>::new_with_format(dir.path(), Format::default())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // new stores are recorded in the default format
+    let dir = tempdir().unwrap();
+    let store = Store::<Blake2b>::new(dir.path()).unwrap();
+    assert_eq!(store.format(), Format::default());
+    drop(store);
+    let err = Store::<Blake2b>::new_with_format(dir.path(), v1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn snapshot_text_encoding() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let snapshot = store.persist(&mut 42u64).unwrap();
+
+    let hex = snapshot.to_string();
+    assert!(hex.starts_with("blake2b:"));
+
+    let from_hex: SnapshotId<Blake2b> = hex.parse().unwrap();
+    assert_eq!(from_hex.digest(), snapshot.hash());
+
+    let base64 = snapshot.id().to_base64();
+    assert!(base64.starts_with("blake2b:"));
+    assert_ne!(base64, hex);
+
+    let from_base64: SnapshotId<Blake2b> = base64.parse().unwrap();
+    assert_eq!(from_hex, from_base64);
+
+    let restored = store
+        .snapshot_from_digest::<u64>(from_base64.digest())
+        .unwrap();
+    assert_eq!(store.restore(&restored).unwrap(), 42);
+}
+
+#[test]
+fn snapshot_text_encoding_invalid() {
+    let hex = Store::<Blake2b>::ephemeral()
+        .persist(&mut 42u64)
+        .unwrap()
+        .to_string();
+
+    let digest = &hex["blake2b:".len()..];
+    assert_ne!(digest, digest.to_uppercase());
+
+    for invalid in &[
+        digest.to_string(),
+        format!("sha256:{}", digest),
+        format!("blake2b:{}", &digest[2..]),
+        format!("blake2b:zz{}", &digest[2..]),
+        format!("blake2b:+{}", &digest[1..]),
+        format!("blake2b:{}", digest.to_uppercase()),
+        format!("blake2b:{}0", digest),
+    ] {
+        assert!(invalid.parse::<SnapshotId<Blake2b>>().is_err());
+    }
+}
+
+#[test]
+fn snapshot_from_unknown_digest() {
+    let store = Store::<Blake2b>::ephemeral();
+    let other = Store::<Blake2b>::ephemeral();
+
+    let snapshot = other.persist(&mut 42u64).unwrap();
+
+    let err = store
+        .snapshot_from_digest::<u64>(snapshot.hash())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn decoding_limits() {
+    let store = Store::<Blake2b>::ephemeral();
+    assert_eq!(store.limits(), Limits::untrusted());
+
+    let v1 = Store::<Blake2b>::ephemeral_with_format(Format {
+        version: EncodingVersion::V1,
+        ..Format::default()
+    });
+
+    // A huge length prefix must not be trusted, in either encoding version
+    let huge = store.persist(&mut u64::max_value()).unwrap().into_hash();
+    let huge_v1 = v1
+        .persist(&mut Varint(u64::max_value()))
+        .unwrap()
+        .into_hash();
+    for (store, huge) in [(&store, huge), (&v1, huge_v1)].iter() {
+        let err = store.snapshot_from_digest::<Vec<u8>>(huge).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = store.snapshot_from_digest::<Vec<()>>(huge).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = store.snapshot_from_digest::<String>(huge).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    let nested = store.persist(&mut vec![vec![vec![1u8, 2, 3]]]).unwrap();
+    let bytes = store.persist(&mut vec![0u8; 100]).unwrap();
+
+    store.set_limits(Limits {
+        max_len: 10,
+        ..Limits::untrusted()
+    });
+    let err = store.restore(&bytes).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    store.set_limits(Limits {
+        max_bytes: 64,
+        ..Limits::untrusted()
+    });
+    let err = store.restore(&bytes).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    store.set_limits(Limits {
+        max_depth: 2,
+        ..Limits::untrusted()
+    });
+    let err = store.restore(&nested).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    store.set_limits(Limits {
+        max_depth: 3,
+        ..Limits::untrusted()
+    });
+    assert_eq!(store.restore(&nested).unwrap(), vec![vec![vec![1, 2, 3]]]);
+
+    store.set_limits(Limits::unbounded());
+    assert_eq!(store.restore(&bytes).unwrap(), vec![0u8; 100]);
+}
+
+#[test]
+fn nesting_depth() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let tuples = store.persist(&mut (1u8, (2u8, (3u8,)))).unwrap();
+    let rcs = store.persist(&mut Rc::new(Rc::new(Rc::new(1u8)))).unwrap();
+    let arcs = store
+        .persist(&mut Arc::new(Arc::new(Arc::new(1u8))))
+        .unwrap();
+    let mut map = BTreeMap::new();
+    map.insert(1u8, BTreeMap::new());
+    map.get_mut(&1).unwrap().insert(2u8, BTreeMap::new());
+    map.get_mut(&1)
+        .unwrap()
+        .get_mut(&2)
+        .unwrap()
+        .insert(3u8, 4u8);
+    let maps = store.persist(&mut map.clone()).unwrap();
+
+    store.set_limits(Limits {
+        max_depth: 2,
+        ..Limits::untrusted()
+    });
+    assert!(store.restore(&tuples).is_err());
+    assert!(store.restore(&rcs).is_err());
+    assert!(store.restore(&arcs).is_err());
+    assert!(store.restore(&maps).is_err());
+
+    store.set_limits(Limits {
+        max_depth: 3,
+        ..Limits::untrusted()
+    });
+    assert_eq!(store.restore(&tuples).unwrap(), (1, (2, (3,))));
+    assert_eq!(*store.restore(&rcs).unwrap(), Rc::new(Rc::new(1)));
+    assert_eq!(*store.restore(&arcs).unwrap(), Arc::new(Arc::new(1)));
+    assert_eq!(store.restore(&maps).unwrap(), map);
+}
+
+// Decodes any non-zero byte as `true`
+#[derive(Clone, Debug, PartialEq)]
+struct Lenient(bool);
+
+impl<H: ByteHash> Content<H> for Lenient {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_all(&[self.0 as u8])
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut byte = [0u8];
+        source.read_exact(&mut byte)?;
+        Ok(Lenient(byte[0] != 0))
+    }
+}
+
+#[test]
+fn trailing_bytes() {
+    let dir = tempdir().unwrap();
+    let disk = Store::<Blake2b>::new(dir.path()).unwrap();
+    let mem = Store::<Blake2b>::ephemeral();
+
+    for store in &[disk, mem] {
+        let pair = store.persist(&mut (1u32, 2u32)).unwrap();
+        // followed by another value in the data file of the disk store
+        let single = store.persist(&mut 3u32).unwrap();
+        let err = store.snapshot_from_digest::<u32>(pair.hash()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(store.restore(&single).unwrap(), 3);
+    }
+}
+
+#[test]
+fn canonical_encoding() {
+    let store = Store::<Blake2b>::ephemeral();
+
+    let canonical = store.persist(&mut 1u8).unwrap();
+    let aliased = store.persist(&mut 2u8).unwrap();
+
+    let lenient = store
+        .snapshot_from_digest::<Lenient>(aliased.hash())
+        .unwrap();
+    assert_eq!(store.restore(&lenient).unwrap(), Lenient(true));
+
+    store.set_canonical(true);
+
+    assert!(store
+        .snapshot_from_digest::<Lenient>(canonical.hash())
+        .is_ok());
+    let err = store
+        .snapshot_from_digest::<Lenient>(aliased.hash())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn large_nodes() {
+    let dir = tempdir().unwrap();
+    let disk = Store::<Blake2b>::new(dir.path()).unwrap();
+    let mem = Store::<Blake2b>::ephemeral();
+
+    // larger than what is kept in memory while writing
+    let mut large: Vec<u8> = (0..3 * 1024 * 1024).map(|i| i as u8).collect();
+
+    let dry = Link::<_, Blake2b>::new(large.clone()).hash().unwrap();
+
+    for store in &[disk, mem] {
+        let snapshot = store.persist(&mut large).unwrap();
+        assert_eq!(snapshot.hash(), &dry);
+        assert_eq!(store.restore(&snapshot).unwrap(), large);
+    }
+}
+
+#[test]
+fn disk_store_many_nodes() {
+    use kelvin::Void;
+    use kelvin_hamt::HAMT;
+
+    let dir = tempdir().unwrap();
+    let store = Store::<Blake2b>::new(dir.path()).unwrap();
+
+    let snapshots: Vec<_> = (0..256u64)
+        .map(|i| store.persist(&mut vec![i; i as usize]).unwrap())
+        .collect();
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        assert_eq!(store.restore(snapshot).unwrap(), vec![i as u64; i]);
+    }
+
+    // a structure of many nodes, each followed by others in the data file
+    let mut hamt = HAMT::<_, _, Void, Blake2b>::new();
+    for i in 0..1024u32 {
+        hamt.insert(i, i).unwrap();
+    }
+    let snapshot = store.persist(&mut hamt).unwrap();
+    let restored = store.restore(&snapshot).unwrap();
+    for i in 0..1024u32 {
+        assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+    }
+}